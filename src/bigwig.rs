@@ -0,0 +1,625 @@
+use std::io::{Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use anyhow::{bail, ensure, Result};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+const BIGWIG_MAGIC: u32 = 0x888F_FC26;
+const CHROM_TREE_MAGIC: u32 = 0x78CA_8C91;
+const INDEX_MAGIC: u32 = 0x2468_ACE0;
+const VERSION: u16 = 4;
+
+// intervals in each compressed block, and children of each index node,
+// the same as bedGraphToBigWig
+const ITEMS_PER_SLOT: usize = 1024;
+const BLOCK_SIZE: usize = 256;
+
+// each zoom level summarises bins 4 times wider than the one before
+const ZOOM_INCREMENT: u64 = 4;
+const MAX_ZOOM_LEVELS: usize = 10;
+
+// the header, the zoom level headers and the total summary come first,
+// and are filled in once everything else is written
+const HEADER_SIZE: u64 = 64;
+const ZOOM_HEADER_SIZE: u64 = 24;
+const SUMMARY_SIZE: u64 = 40;
+const DATA_OFFSET: u64 = HEADER_SIZE + MAX_ZOOM_LEVELS as u64 * ZOOM_HEADER_SIZE + SUMMARY_SIZE;
+
+/// Writes a bigWig file from bedGraph intervals, as they come.
+///
+/// Intervals have to be in order and not overlap within a chromosome, and
+/// each chromosome's intervals have to come together, but chromosomes can
+/// come in any order. Blocks of intervals are compressed and written
+/// straight away; the chromosome list, the indexes and the zoom levels
+/// are written by `finish`.
+pub struct BigWigWriter<W: Write + Seek> {
+    out: W,
+    // where the next byte is written
+    pos: u64,
+    // the name and length of each chromosome, in the order they came
+    chroms: Vec<(String, u32)>,
+    // the intervals not yet written, all on the last chromosome
+    section: Vec<(u32, u32, f32)>,
+    // the end of the last interval on the last chromosome
+    last_end: u32,
+    // the blocks of intervals written so far
+    blocks: Vec<Block>,
+    zooms: Vec<Zoom>,
+    summary: Summary,
+    // the largest block before compression, which readers allocate for
+    max_block: usize,
+}
+
+// (chromosome id, base)
+type Pos = (u32, u32);
+
+// a compressed block of the file, and the span of the genome it covers
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    start: Pos,
+    end: Pos,
+    offset: u64,
+    size: u64,
+}
+
+// the values over some bases
+#[derive(Debug, Clone, Copy)]
+struct Summary {
+    bases: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+    sum_squares: f64,
+}
+
+impl Summary {
+    fn new() -> Self {
+        Self {
+            bases: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+            sum_squares: 0.0,
+        }
+    }
+
+    fn add(&mut self, value: f64, bases: u64) {
+        self.bases += bases;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value * bases as f64;
+        self.sum_squares += value * value * bases as f64;
+    }
+}
+
+// the summaries of fixed width bins of the genome
+struct Zoom {
+    reduction: u64,
+    // (chromosome id, bin start, bin end, summary)
+    records: Vec<(u32, u32, u32, Summary)>,
+}
+
+impl Zoom {
+    fn add(&mut self, chrom: (u32, u32), start: u32, end: u32, value: f64) {
+        let (id, chrom_len) = chrom;
+        let mut pos = start as u64;
+        while pos < end as u64 {
+            let bin_start = pos / self.reduction * self.reduction;
+            let bin_end = (bin_start + self.reduction).min(chrom_len as u64);
+            let overlap_end = bin_end.min(end as u64);
+            match self.records.last_mut() {
+                Some((last_id, last_start, _, summary))
+                    if *last_id == id && *last_start as u64 == bin_start =>
+                {
+                    summary.add(value, overlap_end - pos)
+                }
+                _ => {
+                    let mut summary = Summary::new();
+                    summary.add(value, overlap_end - pos);
+                    self.records
+                        .push((id, bin_start as u32, bin_end as u32, summary));
+                }
+            }
+            pos = overlap_end;
+        }
+    }
+}
+
+impl<W: Write + Seek> BigWigWriter<W> {
+    /// Start a bigWig whose intervals are mostly `span` bases long, which
+    /// sets the resolution of the first zoom level.
+    pub fn new(mut out: W, span: usize) -> Result<Self> {
+        // leave room for the headers
+        out.write_all(&vec![0; DATA_OFFSET as usize])?;
+        // the number of blocks, filled in later
+        out.write_all(&0u64.to_le_bytes())?;
+
+        let mut reduction = span.max(1) as u64 * ZOOM_INCREMENT;
+        let mut zooms = Vec::new();
+        while zooms.len() < MAX_ZOOM_LEVELS && reduction <= u32::MAX as u64 {
+            zooms.push(Zoom {
+                reduction,
+                records: Vec::new(),
+            });
+            reduction *= ZOOM_INCREMENT;
+        }
+        Ok(Self {
+            out,
+            pos: DATA_OFFSET + 8,
+            chroms: Vec::new(),
+            section: Vec::new(),
+            last_end: 0,
+            blocks: Vec::new(),
+            zooms,
+            summary: Summary::new(),
+            max_block: 0,
+        })
+    }
+
+    /// Add the interval `start..end` of `chrom`, which is `chrom_len` bases long.
+    pub fn add(
+        &mut self,
+        chrom: &str,
+        chrom_len: usize,
+        start: usize,
+        end: usize,
+        value: f32,
+    ) -> Result<()> {
+        ensure!(
+            chrom_len <= u32::MAX as usize,
+            "{chrom} is too long for a bigWig ({chrom_len} bp)"
+        );
+        ensure!(
+            start <= end && end <= chrom_len,
+            "interval {start}-{end} is outside {chrom}"
+        );
+        if start == end {
+            return Ok(());
+        }
+        if self.chroms.last().map(|(name, _)| name.as_str()) != Some(chrom) {
+            if self.chroms.iter().any(|(name, _)| name == chrom) {
+                bail!("the intervals of {chrom} have to come together in a bigWig");
+            }
+            self.write_section()?;
+            self.chroms.push((chrom.to_string(), chrom_len as u32));
+            self.last_end = 0;
+        }
+        ensure!(
+            start >= self.last_end as usize,
+            "intervals on {chrom} overlap or are out of order, which a bigWig can't hold"
+        );
+        let (start, end) = (start as u32, end as u32);
+        self.last_end = end;
+
+        let id = (self.chroms.len() - 1) as u32;
+        self.summary.add(value as f64, (end - start) as u64);
+        for zoom in &mut self.zooms {
+            zoom.add((id, chrom_len as u32), start, end, value as f64);
+        }
+        self.section.push((start, end, value));
+        if self.section.len() == ITEMS_PER_SLOT {
+            self.write_section()?;
+        }
+        Ok(())
+    }
+
+    /// Write the chromosome list, the index and the zoom levels, then go
+    /// back and fill in the headers.
+    pub fn finish(mut self) -> Result<W> {
+        self.write_section()?;
+        let data_end = self.pos;
+
+        let chrom_tree = self.pos;
+        self.write_chrom_tree()?;
+        let index = self.pos;
+        let blocks = std::mem::take(&mut self.blocks);
+        self.write_index(&blocks, data_end)?;
+
+        // levels coarser than the longest chromosome don't summarise anything
+        let longest = self.chroms.iter().map(|&(_, len)| len as u64).max();
+        let zooms = std::mem::take(&mut self.zooms);
+        let mut zoom_headers = Vec::new();
+        for (i, zoom) in zooms.into_iter().enumerate() {
+            if i > 0 && longest.is_some_and(|longest| zoom.reduction > longest) {
+                break;
+            }
+            zoom_headers.push(self.write_zoom(zoom)?);
+        }
+
+        let mut header = Vec::with_capacity(DATA_OFFSET as usize);
+        header.extend(BIGWIG_MAGIC.to_le_bytes());
+        header.extend(VERSION.to_le_bytes());
+        header.extend((zoom_headers.len() as u16).to_le_bytes());
+        header.extend(chrom_tree.to_le_bytes());
+        header.extend(DATA_OFFSET.to_le_bytes());
+        header.extend(index.to_le_bytes());
+        // no bed fields or autoSql in a bigWig
+        header.extend(0u16.to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend(0u64.to_le_bytes());
+        let summary_offset = HEADER_SIZE + MAX_ZOOM_LEVELS as u64 * ZOOM_HEADER_SIZE;
+        header.extend(summary_offset.to_le_bytes());
+        header.extend((self.max_block as u32).to_le_bytes());
+        // no extension header
+        header.extend(0u64.to_le_bytes());
+        for (reduction, data, index) in zoom_headers {
+            header.extend((reduction as u32).to_le_bytes());
+            header.extend(0u32.to_le_bytes());
+            header.extend(data.to_le_bytes());
+            header.extend(index.to_le_bytes());
+        }
+        header.resize(summary_offset as usize, 0);
+
+        let summary = self.summary;
+        let (min, max) = match summary.bases {
+            0 => (0.0, 0.0),
+            _ => (summary.min, summary.max),
+        };
+        header.extend(summary.bases.to_le_bytes());
+        header.extend(min.to_le_bytes());
+        header.extend(max.to_le_bytes());
+        header.extend(summary.sum.to_le_bytes());
+        header.extend(summary.sum_squares.to_le_bytes());
+        header.extend((blocks.len() as u64).to_le_bytes());
+
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&header)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.out.write_all(bytes)?;
+        self.pos += bytes.len() as u64;
+        Ok(())
+    }
+
+    // compress and write a block, returning where it went
+    fn write_block(&mut self, bytes: &[u8]) -> Result<(u64, u64)> {
+        self.max_block = self.max_block.max(bytes.len());
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes)?;
+        let compressed = encoder.finish()?;
+        let offset = self.pos;
+        self.write(&compressed)?;
+        Ok((offset, compressed.len() as u64))
+    }
+
+    // write the waiting intervals as one bedGraph section
+    fn write_section(&mut self) -> Result<()> {
+        let Some(&(first, _, _)) = self.section.first() else {
+            return Ok(());
+        };
+        let (_, last, _) = self.section[self.section.len() - 1];
+        let id = (self.chroms.len() - 1) as u32;
+
+        let mut bytes = Vec::with_capacity(24 + 12 * self.section.len());
+        bytes.extend(id.to_le_bytes());
+        bytes.extend(first.to_le_bytes());
+        bytes.extend(last.to_le_bytes());
+        // no step or span, they're only for fixed and variable step sections
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+        // 1 is bedGraph
+        bytes.push(1);
+        bytes.push(0);
+        bytes.extend((self.section.len() as u16).to_le_bytes());
+        for &(start, end, value) in &self.section {
+            bytes.extend(start.to_le_bytes());
+            bytes.extend(end.to_le_bytes());
+            bytes.extend(value.to_le_bytes());
+        }
+        self.section.clear();
+
+        let (offset, size) = self.write_block(&bytes)?;
+        self.blocks.push(Block {
+            start: (id, first),
+            end: (id, last),
+            offset,
+            size,
+        });
+        Ok(())
+    }
+
+    // the names of the chromosomes as a B+ tree, to look up their ids
+    fn write_chrom_tree(&mut self) -> Result<()> {
+        let mut chroms: Vec<(&[u8], u32, u32)> = self
+            .chroms
+            .iter()
+            .enumerate()
+            .map(|(id, (name, len))| (name.as_bytes(), id as u32, *len))
+            .collect();
+        chroms.sort_unstable();
+        let key_size = chroms.iter().map(|c| c.0.len()).max().unwrap_or(1);
+        let block_size = BLOCK_SIZE.min(chroms.len()).max(1);
+
+        let mut bytes = Vec::new();
+        bytes.extend(CHROM_TREE_MAGIC.to_le_bytes());
+        bytes.extend((block_size as u32).to_le_bytes());
+        bytes.extend((key_size as u32).to_le_bytes());
+        // the id and length
+        bytes.extend(8u32.to_le_bytes());
+        bytes.extend((chroms.len() as u64).to_le_bytes());
+        bytes.extend(0u64.to_le_bytes());
+
+        let key = |name: &[u8]| {
+            let mut key = name.to_vec();
+            key.resize(key_size, 0);
+            key
+        };
+        let levels = tree_levels(chroms.len(), block_size);
+        // every leaf item and every child pointer is a key and 8 bytes
+        let offsets = node_offsets(&levels, self.pos + bytes.len() as u64, |_, count| {
+            4 + (key_size as u64 + 8) * count as u64
+        });
+        for (depth, level) in levels.iter().enumerate().rev() {
+            for node in level {
+                bytes.push((depth == 0) as u8);
+                bytes.push(0);
+                bytes.extend((node.len() as u16).to_le_bytes());
+                for child in node.clone() {
+                    match depth {
+                        0 => {
+                            let (name, id, len) = chroms[child];
+                            bytes.extend(key(name));
+                            bytes.extend(id.to_le_bytes());
+                            bytes.extend(len.to_le_bytes());
+                        }
+                        _ => {
+                            // the first chromosome under the child
+                            let first = first_item(&levels[..depth], child);
+                            bytes.extend(key(chroms[first].0));
+                            bytes.extend(offsets[depth - 1][child].to_le_bytes());
+                        }
+                    }
+                }
+            }
+        }
+        self.write(&bytes)
+    }
+
+    // an R-tree of the blocks, to find those overlapping a region
+    fn write_index(&mut self, blocks: &[Block], data_end: u64) -> Result<()> {
+        let levels = tree_levels(blocks.len(), BLOCK_SIZE);
+        // the span of each node at each level
+        let mut spans: Vec<Vec<(Pos, Pos)>> =
+            vec![blocks.iter().map(|b| (b.start, b.end)).collect()];
+        for level in &levels[..levels.len() - 1] {
+            let below = spans.last().expect("starts with the blocks");
+            let level_spans = level
+                .iter()
+                .map(|node| {
+                    let start = below[node.clone()].iter().map(|s| s.0).min();
+                    let end = below[node.clone()].iter().map(|s| s.1).max();
+                    (start.unwrap_or_default(), end.unwrap_or_default())
+                })
+                .collect();
+            spans.push(level_spans);
+        }
+
+        let start = blocks.iter().map(|b| b.start).min().unwrap_or_default();
+        let end = blocks.iter().map(|b| b.end).max().unwrap_or_default();
+        let mut bytes = Vec::new();
+        bytes.extend(INDEX_MAGIC.to_le_bytes());
+        bytes.extend((BLOCK_SIZE as u32).to_le_bytes());
+        bytes.extend((blocks.len() as u64).to_le_bytes());
+        bytes.extend(start.0.to_le_bytes());
+        bytes.extend(start.1.to_le_bytes());
+        bytes.extend(end.0.to_le_bytes());
+        bytes.extend(end.1.to_le_bytes());
+        bytes.extend(data_end.to_le_bytes());
+        bytes.extend((ITEMS_PER_SLOT as u32).to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+
+        // leaf items point at a block and say how big it is, the rest
+        // point at a child node
+        let offsets = node_offsets(&levels, self.pos + bytes.len() as u64, |depth, count| {
+            let item = if depth == 0 { 32 } else { 24 };
+            4 + item * count as u64
+        });
+        for (depth, level) in levels.iter().enumerate().rev() {
+            for node in level {
+                bytes.push((depth == 0) as u8);
+                bytes.push(0);
+                bytes.extend((node.len() as u16).to_le_bytes());
+                for child in node.clone() {
+                    let (start, end) = spans[depth][child];
+                    bytes.extend(start.0.to_le_bytes());
+                    bytes.extend(start.1.to_le_bytes());
+                    bytes.extend(end.0.to_le_bytes());
+                    bytes.extend(end.1.to_le_bytes());
+                    match depth {
+                        0 => {
+                            bytes.extend(blocks[child].offset.to_le_bytes());
+                            bytes.extend(blocks[child].size.to_le_bytes());
+                        }
+                        _ => bytes.extend(offsets[depth - 1][child].to_le_bytes()),
+                    }
+                }
+            }
+        }
+        self.write(&bytes)
+    }
+
+    // write a zoom level's summaries and their index, returning its
+    // reduction, and where the data and the index start
+    fn write_zoom(&mut self, zoom: Zoom) -> Result<(u64, u64, u64)> {
+        let data = self.pos;
+        self.write(&(zoom.records.len() as u32).to_le_bytes())?;
+
+        let mut blocks = Vec::new();
+        for records in zoom.records.chunks(ITEMS_PER_SLOT) {
+            let mut bytes = Vec::with_capacity(32 * records.len());
+            for &(id, start, end, summary) in records {
+                bytes.extend(id.to_le_bytes());
+                bytes.extend(start.to_le_bytes());
+                bytes.extend(end.to_le_bytes());
+                bytes.extend((summary.bases as u32).to_le_bytes());
+                bytes.extend((summary.min as f32).to_le_bytes());
+                bytes.extend((summary.max as f32).to_le_bytes());
+                bytes.extend((summary.sum as f32).to_le_bytes());
+                bytes.extend((summary.sum_squares as f32).to_le_bytes());
+            }
+            let (offset, size) = self.write_block(&bytes)?;
+            let (first, last) = (records[0], records[records.len() - 1]);
+            blocks.push(Block {
+                start: (first.0, first.1),
+                end: (last.0, last.2),
+                offset,
+                size,
+            });
+        }
+        let index = self.pos;
+        self.write_index(&blocks, index)?;
+        Ok((zoom.reduction, data, index))
+    }
+}
+
+// the nodes of a tree over `items`, leaves first and the root last. each
+// node is the range of nodes (or items, for leaves) below it.
+fn tree_levels(items: usize, block_size: usize) -> Vec<Vec<Range<usize>>> {
+    let chunks = |n: usize| -> Vec<Range<usize>> {
+        match n {
+            // an empty tree is a single empty leaf
+            0 => vec![Range::default()],
+            n => (0..n)
+                .step_by(block_size)
+                .map(|i| i..(i + block_size).min(n))
+                .collect(),
+        }
+    };
+    let mut levels = vec![chunks(items)];
+    while levels[levels.len() - 1].len() > 1 {
+        levels.push(chunks(levels[levels.len() - 1].len()));
+    }
+    levels
+}
+
+// the offset of each node when written from `start`, root first, given
+// the size of a node from its depth and number of children
+fn node_offsets(
+    levels: &[Vec<Range<usize>>],
+    start: u64,
+    node_size: impl Fn(usize, usize) -> u64,
+) -> Vec<Vec<u64>> {
+    let mut offsets = vec![Vec::new(); levels.len()];
+    let mut pos = start;
+    for (depth, level) in levels.iter().enumerate().rev() {
+        for node in level {
+            offsets[depth].push(pos);
+            pos += node_size(depth, node.len());
+        }
+    }
+    offsets
+}
+
+// the first item under the `node`th node of the top of `levels`
+fn first_item(levels: &[Vec<Range<usize>>], node: usize) -> usize {
+    levels
+        .iter()
+        .rev()
+        .fold(node, |node, level| level[node].start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::{Cursor, Read};
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+    }
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+    fn u64_at(bytes: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_tree_levels() {
+        assert_eq!(tree_levels(0, 4), [[Range::default()]]);
+        let levels = tree_levels(3, 4);
+        assert_eq!((levels.len(), levels[0].len()), (1, 1));
+        assert_eq!(levels[0][0], 0..3);
+        let levels = tree_levels(10, 2);
+        assert_eq!(levels.len(), 4);
+        assert_eq!(levels[0], [0..2, 2..4, 4..6, 6..8, 8..10]);
+        assert_eq!(levels[1], [0..2, 2..4, 4..5]);
+        assert_eq!(levels[3][0], 0..2);
+        assert_eq!(first_item(&levels[..2], 2), 8);
+
+        // root first, then each level in turn
+        let offsets = node_offsets(&levels, 100, |_, count| count as u64);
+        assert_eq!(offsets[3], [100]);
+        assert_eq!(offsets[2], [102, 104]);
+        assert_eq!(offsets[0][0], 100 + 2 + 3 + 5);
+    }
+
+    #[test]
+    fn test_bigwig_layout() {
+        let mut writer = BigWigWriter::new(Cursor::new(Vec::new()), 10).unwrap();
+        // more intervals than fit in one block
+        for i in 0..1500 {
+            writer
+                .add("chr2", 20_000, i * 10, i * 10 + 10, i as f32)
+                .unwrap();
+        }
+        writer.add("chr1", 100, 0, 50, 0.5).unwrap();
+        writer.add("chr1", 100, 50, 100, 1.5).unwrap();
+        assert!(writer.add("chr1", 100, 90, 100, 1.0).is_err());
+        assert!(writer.add("chr2", 20_000, 15_000, 15_010, 1.0).is_err());
+        let bytes = writer.finish().unwrap().into_inner();
+
+        assert_eq!(u32_at(&bytes, 0), BIGWIG_MAGIC);
+        assert_eq!(u16_at(&bytes, 4), VERSION);
+        // 40, 160, 640, 2560 and 10240 bp bins, but none wider than chr2
+        assert_eq!(u16_at(&bytes, 6), 5);
+        let chrom_tree = u64_at(&bytes, 8) as usize;
+        let data = u64_at(&bytes, 16) as usize;
+        let index = u64_at(&bytes, 24) as usize;
+        assert_eq!(data, DATA_OFFSET as usize);
+        // two blocks on chr2, and one on chr1
+        assert_eq!(u64_at(&bytes, data), 3);
+
+        // the chromosomes are sorted by name, but keep the ids they came with
+        assert_eq!(u32_at(&bytes, chrom_tree), CHROM_TREE_MAGIC);
+        assert_eq!(u64_at(&bytes, chrom_tree + 16), 2);
+        let leaf = chrom_tree + 32;
+        assert_eq!((bytes[leaf], u16_at(&bytes, leaf + 2)), (1, 2));
+        assert_eq!(&bytes[leaf + 4..leaf + 8], b"chr1");
+        assert_eq!(u32_at(&bytes, leaf + 8), 1);
+        assert_eq!(u32_at(&bytes, leaf + 12), 100);
+        assert_eq!(&bytes[leaf + 16..leaf + 20], b"chr2");
+
+        // a single leaf index node, pointing at the first block
+        assert_eq!(u32_at(&bytes, index), INDEX_MAGIC);
+        assert_eq!(u64_at(&bytes, index + 8), 3);
+        let node = index + 48;
+        assert_eq!((bytes[node], u16_at(&bytes, node + 2)), (1, 3));
+        assert_eq!(u32_at(&bytes, node + 4 + 12), 10240);
+        let offset = u64_at(&bytes, node + 4 + 16) as usize;
+        let size = u64_at(&bytes, node + 4 + 24) as usize;
+        assert_eq!(offset, data + 8);
+
+        let mut block = Vec::new();
+        ZlibDecoder::new(&bytes[offset..offset + size])
+            .read_to_end(&mut block)
+            .unwrap();
+        assert_eq!(block.len(), 24 + 12 * 1024);
+        assert_eq!(block[20], 1);
+        assert_eq!(u16_at(&block, 22), 1024);
+        assert_eq!((u32_at(&block, 24 + 12), u32_at(&block, 28 + 12)), (10, 20));
+        assert_eq!(
+            f32::from_le_bytes(block[32 + 12..36 + 12].try_into().unwrap()),
+            1.0
+        );
+
+        // every base is covered once
+        let summary = HEADER_SIZE as usize + MAX_ZOOM_LEVELS * ZOOM_HEADER_SIZE as usize;
+        assert_eq!(u64_at(&bytes, summary), 15_100);
+        assert_eq!(u64_at(&bytes, u64_at(&bytes, 44) as usize), 15_100);
+    }
+}
@@ -0,0 +1,570 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use arrow::array::{
+    ArrayRef, DictionaryArray, Float32Array, Int32Array, StringArray, UInt32Array, UInt64Array,
+};
+use arrow::datatypes::{DataType, Field, Int32Type, Schema, SchemaRef};
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+
+use crate::config::Config;
+use crate::fw::{Entry, RecordWindows, WriteWindows};
+use crate::kmer_maps::{self, KmerMap};
+use crate::outpath::Format;
+
+// parquet row groups are buffered until written, so each holds at most
+// this many rows, and about this many bytes. the widest k-mer table has
+// 4,096 columns, so rather than a gigabyte its groups are ~2,000 rows.
+const ROW_GROUP_SIZE: usize = 1 << 16;
+const ROW_GROUP_BYTES: usize = 64 << 20;
+
+// the rows in each row group of a table this many columns wide, taking
+// every value as 8 bytes to be safe
+fn row_group_rows(columns: usize) -> usize {
+    (ROW_GROUP_BYTES / (columns.max(1) * 8)).clamp(1, ROW_GROUP_SIZE)
+}
+
+/// The same tables as the TSV output, but as Parquet or Arrow IPC files
+/// with typed columns: u64 coordinates, f32 statistics and u32 counts.
+///
+/// Each record is written as one record batch. Sequence IDs (and
+/// descriptions) are dictionary encoded in Parquet. Arrow files allow only
+/// one dictionary per column, and the IDs aren't known until they're read,
+/// so there they are plain strings.
+pub struct ColumnarOutput<W: Write + Send> {
+    format: Format,
+    windows: Table<W>,
+    mononucs: Table<W>,
+    // the counts of each k-mer size, in the order of `Entry::kmers`
+    kmers: Vec<(KmerMap, Table<W>)>,
+    // karlin's rho of each dinucleotide
+    rho: Option<(KmerMap, Table<W>)>,
+    // the counts of each of this many motifs
+    motifs: Option<(usize, Table<W>)>,
+    // the tandem repeat of each window
+    tandem: Option<Table<W>>,
+    // add a column for the fasta header descriptions
+    description: bool,
+    // add a column for the context-tree weighting
+    ctw: bool,
+    // add columns for the gaps
+    gaps: bool,
+    // add columns for the soft-masked runs
+    masked_runs: bool,
+    // add columns for the homopolymers and short tandem repeats
+    strs: bool,
+    // add columns for the DUST and Wootton-Federhen complexity
+    complexity: bool,
+}
+
+impl<W: Write + Send> ColumnarOutput<W> {
+    /// Start the tables of `config` in `files`: the windows, the
+    /// mononucleotides, one for each k-mer size, then the rho, motif and
+    /// tandem repeat tables.
+    pub fn new(files: Vec<W>, config: &Config) -> Result<Self> {
+        let (format, kmer_maps, description) =
+            (config.format, &config.kmer_maps, config.description);
+        let (ctw, gaps, masked_runs, strs) =
+            (config.ctw, config.gaps, config.masked_runs, config.strs);
+        let mut files = files.into_iter();
+        let mut table = |fields: Vec<Field>| {
+            let mut all = id_fields(format, description);
+            all.extend(fields);
+            Table::new(format, files.next().expect("one file per table"), all)
+        };
+
+        let mut stats = vec!["GC_prop", "GC_skew", "AT_skew", "Shannon_entropy"];
+        if ctw {
+            stats.push("ctw");
+        }
+        stats.extend([
+            "Prop_Gs",
+            "Prop_Cs",
+            "Prop_As",
+            "Prop_Ts",
+            "Prop_Ns",
+            "Prop_masked",
+            "CpG_prop",
+            "CpG_oe",
+        ]);
+        let mut fields: Vec<Field> = stats.iter().map(|name| f32_field(name)).collect();
+        if gaps {
+            fields.extend([u32_field("gaps"), u32_field("gap_bases")]);
+        }
+        if masked_runs {
+            fields.extend([
+                u32_field("masked_runs"),
+                u32_field("longest_masked_run"),
+                f32_field("mean_masked_run"),
+            ]);
+        }
+        if strs {
+            fields.extend([
+                u32_field("longest_homopolymer"),
+                u32_field("homopolymers"),
+                f32_field("STR_coverage"),
+            ]);
+        }
+        if config.complexity {
+            fields.extend([f32_field("DUST"), f32_field("WF_complexity")]);
+        }
+        fields.extend(
+            kmer_maps
+                .iter()
+                .map(|m| f32_field(&format!("{}_Shannon", m.name()))),
+        );
+        let windows = table(fields)?;
+        let mononucs = table(["A", "C", "G", "T", "N"].map(u32_field).to_vec())?;
+
+        let mut kmers = Vec::new();
+        for kmer_map in kmer_maps {
+            let fields = match kmer_map.is_dense() {
+                true => kmer_map
+                    .kmers()
+                    .iter()
+                    .map(|kmer| u32_field(kmer))
+                    .collect(),
+                false => vec![
+                    Field::new("kmer", DataType::Utf8, false),
+                    u32_field("count"),
+                ],
+            };
+            kmers.push((*kmer_map, table(fields)?));
+        }
+        let rho = match config.rho {
+            Some(dinucs) => Some((
+                dinucs,
+                table(dinucs.kmers().iter().map(|kmer| f32_field(kmer)).collect())?,
+            )),
+            None => None,
+        };
+        let motifs = match config.motifs.is_empty() {
+            true => None,
+            false => Some((
+                config.motifs.len(),
+                table(config.motifs.iter().map(|m| u32_field(&m.name)).collect())?,
+            )),
+        };
+
+        let tandem = match config.tandem_repeats {
+            true => Some(table(vec![
+                u32_field("period"),
+                f32_field("coverage"),
+                Field::new("monomer", DataType::Utf8, true),
+            ])?),
+            false => None,
+        };
+
+        Ok(Self {
+            format,
+            windows,
+            mononucs,
+            kmers,
+            rho,
+            motifs,
+            tandem,
+            description,
+            ctw,
+            gaps,
+            masked_runs,
+            strs,
+            complexity: config.complexity,
+        })
+    }
+
+    // the same string on every row
+    fn repeated(&self, value: &str, rows: usize) -> ArrayRef {
+        match self.format {
+            Format::Parquet => {
+                let keys = Int32Array::from(vec![0; rows]);
+                let values = Arc::new(StringArray::from(vec![value]));
+                Arc::new(DictionaryArray::<Int32Type>::new(keys, values))
+            }
+            _ => Arc::new(StringArray::from(vec![value; rows])),
+        }
+    }
+
+    // the id columns of a table with a row for each of `coords`
+    fn id_columns(&self, record: &RecordWindows, coords: &[(usize, usize)]) -> Vec<ArrayRef> {
+        let mut columns = vec![self.repeated(&record.id, coords.len())];
+        if self.description {
+            columns.push(self.repeated(&record.desc, coords.len()));
+        }
+        let starts = coords.iter().map(|&(start, _)| start as u64);
+        let ends = coords.iter().map(|&(_, end)| end as u64);
+        columns.push(Arc::new(UInt64Array::from_iter_values(starts)));
+        columns.push(Arc::new(UInt64Array::from_iter_values(ends)));
+        columns
+    }
+
+    fn write_windows(&mut self, record: &RecordWindows) -> Result<()> {
+        let entries = &record.entries;
+        let coords: Vec<_> = entries.iter().map(|e| (e.start, e.end)).collect();
+        let stat = |f: fn(&Entry) -> f32| -> ArrayRef {
+            Arc::new(Float32Array::from_iter_values(entries.iter().map(f)))
+        };
+
+        let mut columns = self.id_columns(record, &coords);
+        columns.extend([
+            stat(|e| e.gc_proportion),
+            stat(|e| e.gc_skew),
+            stat(|e| e.at_skew),
+            stat(|e| e.shannon_entropy as f32),
+        ]);
+        if self.ctw {
+            columns.push(stat(|e| e.ctw_bpb as f32));
+        }
+        columns.extend([
+            stat(|e| e.g_s),
+            stat(|e| e.c_s),
+            stat(|e| e.a_s),
+            stat(|e| e.t_s),
+            stat(|e| e.n_s),
+            stat(|e| e.masked),
+            stat(|e| e.cpg_s),
+            stat(|e| e.cpg_oe),
+        ]);
+        if self.gaps {
+            let gaps = entries.iter().map(|e| e.gaps as u32);
+            columns.push(Arc::new(UInt32Array::from_iter_values(gaps)));
+            let gap_bases = entries.iter().map(|e| e.gap_bases as u32);
+            columns.push(Arc::new(UInt32Array::from_iter_values(gap_bases)));
+        }
+        if self.masked_runs {
+            let runs = entries.iter().map(|e| e.masked_runs as u32);
+            columns.push(Arc::new(UInt32Array::from_iter_values(runs)));
+            let longest = entries.iter().map(|e| e.longest_masked_run as u32);
+            columns.push(Arc::new(UInt32Array::from_iter_values(longest)));
+            columns.push(stat(|e| e.mean_masked_run));
+        }
+        if self.strs {
+            let longest = entries.iter().map(|e| e.longest_homopolymer as u32);
+            columns.push(Arc::new(UInt32Array::from_iter_values(longest)));
+            let homopolymers = entries.iter().map(|e| e.homopolymers as u32);
+            columns.push(Arc::new(UInt32Array::from_iter_values(homopolymers)));
+            columns.push(stat(|e| e.str_coverage));
+        }
+        if self.complexity {
+            columns.extend([stat(|e| e.dust), stat(|e| e.wf_complexity)]);
+        }
+        for i in 0..self.kmers.len() {
+            let shannon = entries.iter().map(|e| e.kmers[i].shannon as f32);
+            columns.push(Arc::new(Float32Array::from_iter_values(shannon)));
+        }
+        self.windows.write(columns)
+    }
+
+    fn write_kmers(&mut self, record: &RecordWindows) -> Result<()> {
+        let entries = &record.entries;
+        let coords: Vec<_> = entries.iter().map(|e| (e.start, e.end)).collect();
+
+        let mut columns = self.id_columns(record, &coords);
+        for base in 0..5 {
+            let counts = entries.iter().map(|e| e.nuc_counts[base] as u32);
+            columns.push(Arc::new(UInt32Array::from_iter_values(counts)));
+        }
+        self.mononucs.write(columns)?;
+
+        for i in 0..self.kmers.len() {
+            let kmer_map = self.kmers[i].0;
+            let columns = match kmer_map.is_dense() {
+                true => {
+                    let dense: Vec<Vec<u32>> = entries.iter().map(|e| e.kmers[i].dense()).collect();
+                    let mut columns = self.id_columns(record, &coords);
+                    for column in 0..kmer_map.codes().len() {
+                        let counts = dense.iter().map(|counts| counts[column]);
+                        columns.push(Arc::new(UInt32Array::from_iter_values(counts)) as ArrayRef);
+                    }
+                    columns
+                }
+                // a row for each k-mer present in each window
+                false => {
+                    let rows = entries
+                        .iter()
+                        .flat_map(|e| e.kmers[i].counts.iter().map(|&c| ((e.start, e.end), c)));
+                    let (coords, counts): (Vec<_>, Vec<_>) = rows.unzip();
+                    let kmers = counts
+                        .iter()
+                        .map(|&(code, _)| kmer_maps::decode(code, kmer_map.len));
+                    let mut columns = self.id_columns(record, &coords);
+                    columns.push(Arc::new(StringArray::from_iter_values(kmers)));
+                    columns.push(Arc::new(UInt32Array::from_iter_values(
+                        counts.iter().map(|&(_, count)| count),
+                    )));
+                    columns
+                }
+            };
+            self.kmers[i].1.write(columns)?;
+        }
+
+        // the tables with a row per window share their id columns
+        let ids = self.id_columns(record, &coords);
+        if let Some((dinucs, table)) = &mut self.rho {
+            let mut columns = ids.clone();
+            for column in 0..dinucs.codes().len() {
+                let rho = entries.iter().map(|e| e.rho[column]);
+                columns.push(Arc::new(Float32Array::from_iter_values(rho)));
+            }
+            table.write(columns)?;
+        }
+
+        if let Some((motifs, table)) = &mut self.motifs {
+            let mut columns = ids.clone();
+            for column in 0..*motifs {
+                let counts = entries.iter().map(|e| e.motifs[column]);
+                columns.push(Arc::new(UInt32Array::from_iter_values(counts)));
+            }
+            table.write(columns)?;
+        }
+
+        if let Some(table) = &mut self.tandem {
+            let tandem = entries.iter().map(|e| e.tandem.as_ref());
+            let periods = tandem.clone().map(|t| t.map_or(0, |t| t.period as u32));
+            let coverages = tandem.clone().map(|t| t.map_or(0.0, |t| t.coverage));
+            let monomers = tandem.map(|t| t.map(|t| t.monomer.as_str()));
+            let mut columns = ids;
+            columns.extend([
+                Arc::new(UInt32Array::from_iter_values(periods)) as ArrayRef,
+                Arc::new(Float32Array::from_iter_values(coverages)),
+                Arc::new(StringArray::from_iter(monomers)),
+            ]);
+            table.write(columns)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> WriteWindows for ColumnarOutput<W> {
+    fn write_record(&mut self, record: &RecordWindows) -> Result<()> {
+        self.write_windows(record)?;
+        self.write_kmers(record)
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.windows.finish()?;
+        self.mononucs.finish()?;
+        for (_, table) in self.kmers {
+            table.finish()?;
+        }
+        if let Some((_, table)) = self.rho {
+            table.finish()?;
+        }
+        if let Some((_, table)) = self.motifs {
+            table.finish()?;
+        }
+        if let Some(table) = self.tandem {
+            table.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// one output file, written a record batch at a time
+struct Table<W: Write + Send> {
+    schema: SchemaRef,
+    writer: TableWriter<W>,
+}
+
+enum TableWriter<W: Write + Send> {
+    Parquet(ArrowWriter<W>),
+    Arrow(FileWriter<W>),
+}
+
+impl<W: Write + Send> Table<W> {
+    fn new(format: Format, file: W, fields: Vec<Field>) -> Result<Self> {
+        let schema = Arc::new(Schema::new(fields));
+        let writer = match format {
+            Format::Parquet => {
+                let props = WriterProperties::builder()
+                    .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                    .set_max_row_group_size(row_group_rows(schema.fields().len()))
+                    .build();
+                TableWriter::Parquet(ArrowWriter::try_new(file, schema.clone(), Some(props))?)
+            }
+            Format::Arrow => TableWriter::Arrow(FileWriter::try_new(file, &schema)?),
+            Format::Tsv => unreachable!("TSV output is written by fw::Output"),
+        };
+        Ok(Self { schema, writer })
+    }
+
+    fn write(&mut self, columns: Vec<ArrayRef>) -> Result<()> {
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)
+            .context("output columns don't match the schema")?;
+        match &mut self.writer {
+            TableWriter::Parquet(writer) => writer.write(&batch)?,
+            TableWriter::Arrow(writer) => writer.write(&batch)?,
+        }
+        Ok(())
+    }
+
+    // write the footer, and flush the file
+    fn finish(self) -> Result<()> {
+        let mut file = match self.writer {
+            TableWriter::Parquet(writer) => writer.into_inner()?,
+            TableWriter::Arrow(writer) => writer.into_inner()?,
+        };
+        file.flush().context("could not write the output")
+    }
+}
+
+// the first columns of every table
+fn id_fields(format: Format, description: bool) -> Vec<Field> {
+    let mut fields = vec![Field::new("ID", string_type(format), false)];
+    if description {
+        fields.push(Field::new("description", string_type(format), false));
+    }
+    fields.extend([u64_field("start"), u64_field("end")]);
+    fields
+}
+
+fn string_type(format: Format) -> DataType {
+    match format {
+        Format::Parquet => {
+            DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8))
+        }
+        _ => DataType::Utf8,
+    }
+}
+
+fn f32_field(name: &str) -> Field {
+    Field::new(name, DataType::Float32, false)
+}
+
+fn u32_field(name: &str) -> Field {
+    Field::new(name, DataType::UInt32, false)
+}
+
+fn u64_field(name: &str) -> Field {
+    Field::new(name, DataType::UInt64, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::BufWriter;
+
+    use super::*;
+    use arrow::array::AsArray;
+    use arrow::datatypes::UInt32Type;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use crate::fw::window_entries;
+    use crate::windows::SlidingWindows;
+
+    fn record(id: &str, seq: &[u8]) -> RecordWindows {
+        let config = Config::builder()
+            .window_size(10)
+            .kmer_sizes(&[2, 7])
+            .build()
+            .unwrap();
+        let windows = SlidingWindows::new(seq.len(), 10, 10).unwrap();
+        RecordWindows {
+            id: id.to_string(),
+            desc: "No description.".to_string(),
+            len: seq.len(),
+            full_len: seq.len(),
+            entries: window_entries(seq, windows.iter(), &config),
+            totals: None,
+            gaps: None,
+            masked_runs: None,
+            strs: None,
+            dust: None,
+        }
+    }
+
+    // write two records, returning the paths of the tables
+    fn write(format: Format, dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+        let config = Config::builder()
+            .kmer_sizes(&[2, 7])
+            .format(format)
+            .description(true)
+            .build()
+            .unwrap();
+        let paths: Vec<_> = ["freq", "mononuc", "dinuc", "7mer"]
+            .iter()
+            .map(|t| dir.join(format!("{t}.{}", format.extension())))
+            .collect();
+        let files = paths
+            .iter()
+            .map(|p| BufWriter::new(File::create(p).unwrap()))
+            .collect();
+        let mut output: Box<dyn WriteWindows> =
+            Box::new(ColumnarOutput::new(files, &config).unwrap());
+        output
+            .write_record(&record("one", b"ACGTACGTACGTACGTACGTAC"))
+            .unwrap();
+        output.write_record(&record("two", b"CCCCCGGGGG")).unwrap();
+        output.finish().unwrap();
+        paths
+    }
+
+    #[test]
+    fn test_row_group_rows() {
+        // the windows table is narrow enough for full row groups
+        assert_eq!(row_group_rows(30), ROW_GROUP_SIZE);
+        // but 6-mers are cut to keep each row group to ~64 MiB
+        let rows = row_group_rows(3 + 4096);
+        assert!(
+            rows * (3 + 4096) * 8 <= ROW_GROUP_BYTES && rows > 1000,
+            "{rows}"
+        );
+        assert_eq!(row_group_rows(usize::MAX / 8), 1);
+    }
+
+    #[test]
+    fn test_parquet_and_arrow_tables() {
+        let dir = std::env::temp_dir().join(format!("fw_columnar_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for format in [Format::Parquet, Format::Arrow] {
+            let paths = write(format, &dir);
+            let read = |path: &std::path::Path| -> Vec<RecordBatch> {
+                let file = File::open(path).unwrap();
+                match format {
+                    Format::Parquet => ParquetRecordBatchReaderBuilder::try_new(file)
+                        .unwrap()
+                        .build()
+                        .unwrap()
+                        .map(|batch| batch.unwrap())
+                        .collect(),
+                    _ => arrow::ipc::reader::FileReader::try_new(file, None)
+                        .unwrap()
+                        .map(|batch| batch.unwrap())
+                        .collect(),
+                }
+            };
+            let rows =
+                |batches: &[RecordBatch]| batches.iter().map(|b| b.num_rows()).sum::<usize>();
+
+            let windows = read(&paths[0]);
+            assert_eq!(rows(&windows), 4);
+            let schema = windows[0].schema();
+            assert_eq!(schema.field(0).name(), "ID");
+            assert_eq!(schema.field(0).data_type(), &string_type(format));
+            assert_eq!(schema.field(2).data_type(), &DataType::UInt64);
+            assert_eq!(
+                schema.field_with_name("GC_prop").unwrap().data_type(),
+                &DataType::Float32
+            );
+            assert!(schema.field_with_name("7mer_Shannon").is_ok());
+
+            // AC, CG, GT and TA in the first window
+            let dinucs = read(&paths[2]);
+            assert_eq!(dinucs[0].num_columns(), 4 + 16);
+            let cg = dinucs[0].column_by_name("CG").unwrap();
+            assert_eq!(cg.as_primitive::<UInt32Type>().values()[..3], [2, 2, 0]);
+
+            // a row per 7-mer in each window: 4 + 4 + 0 + 4
+            let sevenmers = read(&paths[3]);
+            assert_eq!(rows(&sevenmers), 12);
+            let kmers = sevenmers[0].column_by_name("kmer").unwrap();
+            assert_eq!(kmers.as_string::<i32>().value(0), "ACGTACG");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+use std::io::Write;
+
+use anyhow::Result;
+use rayon::prelude::*;
+
+use crate::fw::{RecordWindows, WriteWindows};
+use crate::kmer_maps::ENCODE;
+use crate::seq_statsu8::ByteCounts;
+
+/// The longest interval DUST masks, and the score above which it does (ten
+/// times the score of [`dust_score`]), unless told otherwise. These are the
+/// defaults of dustmasker and minimap2's sdust.
+pub const DEFAULT_DUST_WINDOW: usize = 64;
+pub const DEFAULT_DUST_THRESHOLD: u32 = 20;
+
+// the code of the triplet starting at each base, or None if it has
+// anything but A, C, G and T in it
+fn triplets(seq: &[u8]) -> impl Iterator<Item = Option<usize>> + '_ {
+    seq.windows(3).map(|t| {
+        t.iter().try_fold(0, |code, &b| match ENCODE[b as usize] {
+            4 => None,
+            base => Some(code << 2 | base as usize),
+        })
+    })
+}
+
+/// The DUST score of `win`: each triplet occurring c times adds c(c-1)/2,
+/// and the sum is divided by one less than the number of triplets. Only
+/// triplets of A, C, G and T (either case) are counted.
+///
+/// Repeats of short units score highly, but the score grows with the window
+/// too, so only compare windows of the same size.
+pub fn dust_score(win: &[u8]) -> f32 {
+    let mut counts = [0u32; 64];
+    let (mut repeats, mut total) = (0u64, 0u64);
+    for code in triplets(win).flatten() {
+        repeats += counts[code] as u64;
+        counts[code] += 1;
+        total += 1;
+    }
+    match total {
+        0 | 1 => 0.0,
+        _ => repeats as f32 / (total - 1) as f32,
+    }
+}
+
+// ln(n!), summed exactly for small n and by Stirling's series above that
+fn ln_factorial(n: u64) -> f64 {
+    if n < 16 {
+        return (2..=n).map(|i| (i as f64).ln()).sum();
+    }
+    let n = n as f64;
+    n * n.ln() - n + 0.5 * (2.0 * std::f64::consts::PI * n).ln() + 1.0 / (12.0 * n)
+        - 1.0 / (360.0 * n.powi(3))
+}
+
+/// The counts of A, C, G and T that the Wootton-Federhen complexity of a
+/// window is made from, in every mode. As for the composition statistics,
+/// only uppercase bases count if `masked`, and either case otherwise.
+pub fn acgt_counts(counts: &ByteCounts, masked: bool) -> [i64; 4] {
+    match masked {
+        true => b"ACGT".map(|base| counts.get(base)),
+        false => counts.acgt(),
+    }
+}
+
+/// The Wootton-Federhen complexity of a sequence with these counts of A, C,
+/// G and T, as in SEG: the log, base 4, of the number of sequences with the
+/// same composition, per base. It runs from 0 for a homopolymer to near 1
+/// for an even mix.
+pub fn wootton_federhen(acgt: &[i64]) -> f32 {
+    let len: i64 = acgt.iter().sum();
+    if len == 0 {
+        return 0.0;
+    }
+    let arrangements = ln_factorial(len as u64)
+        - acgt
+            .iter()
+            .map(|&count| ln_factorial(count as u64))
+            .sum::<f64>();
+    (arrangements / 4f64.ln() / len as f64) as f32
+}
+
+/// The intervals of `seq` masked by symmetric DUST (Morgulis et al. 2006), as
+/// half-open ranges in order. An interval of at most `window` bases is
+/// perfect if its score is above `threshold` / 10 and nothing inside it
+/// scores higher, and the perfect intervals are merged. Triplets with
+/// anything but A, C, G and T in them are never masked.
+pub fn dust_intervals(seq: &[u8], window: usize, threshold: u32) -> Vec<(usize, usize)> {
+    // whether an interval is perfect only depends on the bases in it, so
+    // chunks can be found in parallel, each starting a window early
+    let chunks = seq.len().div_ceil(DUST_CHUNK);
+    let mut intervals: Vec<(usize, usize)> = (0..chunks)
+        .into_par_iter()
+        .flat_map_iter(|c| {
+            let start = (c * DUST_CHUNK).saturating_sub(window);
+            let end = ((c + 1) * DUST_CHUNK).min(seq.len());
+            let from = c * DUST_CHUNK - start;
+            dust_chunk(&seq[start..end], window, threshold, from)
+                .into_iter()
+                .map(move |(s, e)| (s + start, e + start))
+        })
+        .collect();
+    intervals.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(intervals.len());
+    for (start, end) in intervals {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+// how much sequence each parallel chunk of DUST covers
+const DUST_CHUNK: usize = 1 << 20;
+
+// the merged perfect intervals of `seq` ending after `from`
+fn dust_chunk(seq: &[u8], window: usize, threshold: u32, from: usize) -> Vec<(usize, usize)> {
+    // 64 for a triplet with anything but A, C, G and T in it
+    let codes: Vec<u8> = triplets(seq).map(|t| t.unwrap_or(64) as u8).collect();
+    let max_triplets = window.saturating_sub(2).max(1);
+    // the triplet counts, and sum of c(c-1)/2, of the longest interval that
+    // could be masked ending at each triplet
+    let mut window_counts = [0u64; 64];
+    let (mut window_repeats, mut window_first) = (0, 0);
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    // the best score (its sum of c(c-1)/2, and one less than its number of
+    // triplets) of the perfect intervals starting at each triplet so far
+    let mut best_from: Vec<Option<(u64, u64)>> = vec![None; codes.len()];
+    let mut run_start = 0;
+    for last in 0..codes.len() {
+        if codes[last] == 64 {
+            run_start = last + 1;
+            (window_counts, window_repeats, window_first) = ([0; 64], 0, run_start);
+            continue;
+        }
+        let lowest = run_start.max((last + 1).saturating_sub(max_triplets));
+        window_repeats += window_counts[codes[last] as usize];
+        window_counts[codes[last] as usize] += 1;
+        while window_first < lowest {
+            window_counts[codes[window_first] as usize] -= 1;
+            window_repeats -= window_counts[codes[window_first] as usize];
+            window_first += 1;
+        }
+        // an interval scoring above the threshold has fewer pairs of
+        // triplets than this, as it can't have more repeats than the window
+        let reach = match threshold {
+            0 => max_triplets as u64,
+            _ => (window_repeats * 10).div_ceil(threshold as u64),
+        };
+        if reach == 0 {
+            continue;
+        }
+        let lowest = lowest.max(last + 1 - (reach as usize).min(last + 1));
+
+        // extend back from the last triplet, keeping the best score of the
+        // perfect intervals inside, which all end before it
+        let mut counts = [0u64; 64];
+        let mut repeats = 0;
+        let mut best: Option<(u64, u64)> = None;
+        let higher = |(r, l): (u64, u64), best: Option<(u64, u64)>| {
+            best.is_none_or(|(best_r, best_l)| r * best_l >= best_r * l)
+        };
+        for first in (lowest..=last).rev() {
+            let code = codes[first] as usize;
+            repeats += counts[code];
+            counts[code] += 1;
+            if let Some(score) = best_from[first] {
+                if higher(score, best) {
+                    best = Some(score);
+                }
+            }
+            let pairs = (last - first) as u64;
+            if repeats * 10 <= threshold as u64 * pairs || !higher((repeats, pairs), best) {
+                continue;
+            }
+            best = Some((repeats, pairs));
+            if higher((repeats, pairs), best_from[first]) {
+                best_from[first] = best;
+            }
+            if last + 3 > from {
+                // every interval so far ends at or before this one
+                let mut start = first;
+                while let Some(&(s, e)) = merged.last() {
+                    if e < start {
+                        break;
+                    }
+                    start = start.min(s);
+                    merged.pop();
+                }
+                merged.push((start, last + 3));
+            }
+        }
+    }
+    merged
+}
+
+/// The DUST-masked intervals of each sequence, as a BED of
+/// `ID start end length`.
+pub struct Dust<W: Write> {
+    bed: W,
+}
+
+impl<W: Write> Dust<W> {
+    pub fn new(bed: W) -> Self {
+        Self { bed }
+    }
+}
+
+impl<W: Write> WriteWindows for Dust<W> {
+    fn write_record(&mut self, record: &RecordWindows) -> Result<()> {
+        for &(start, end) in record.dust.as_deref().unwrap_or_default() {
+            writeln!(self.bed, "{}\t{start}\t{end}\t{}", record.id, end - start)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.bed.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fixed sequence with no low-complexity stretches
+    const MIXED: &[u8] =
+        b"GATCCTAGTCAGGCTTACGATTGCAAGCTCGTTAGCCATGACGTATGGCACTTGAGCATCTAGCGGTCAATCCGTATGCTA";
+
+    #[test]
+    fn test_scores() {
+        assert_eq!(dust_score(b"ACGT"), 0.0);
+        // 8 AAAs, so 28 pairs over 7
+        assert_eq!(dust_score(b"AAAAAAAAAA"), 4.0);
+        // 6 AAAs, either side of the N
+        assert_eq!(dust_score(b"AAAAANAAAAA"), 3.0);
+
+        assert_eq!(wootton_federhen(&[10, 0, 0, 0]), 0.0);
+        assert!((wootton_federhen(&[1, 1, 1, 1]) - 24f32.ln() / 4f32.ln() / 4.0).abs() < 1e-6);
+        let even = wootton_federhen(&[250, 250, 250, 250]);
+        assert!(even > 0.98 && even < 1.0, "{even}");
+        assert!(wootton_federhen(&[700, 100, 100, 100]) < even);
+        assert_eq!(wootton_federhen(&[0, 0, 0, 0]), 0.0);
+
+        let mut counts = ByteCounts::new();
+        b"AACgtN".iter().for_each(|&b| counts.add(b));
+        assert_eq!(acgt_counts(&counts, false), [2, 1, 1, 1]);
+        assert_eq!(acgt_counts(&counts, true), [2, 1, 0, 0]);
+        assert!((ln_factorial(20) - (2..=20).map(|i| (i as f64).ln()).sum::<f64>()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_dust_intervals() {
+        let mut seq = MIXED.to_vec();
+        seq.extend(b"A".repeat(30));
+        seq.extend(MIXED);
+        seq.extend(b"CA".repeat(20));
+        seq.extend(MIXED);
+        let (a, ca) = (MIXED.len(), 2 * MIXED.len() + 30);
+        let intervals = dust_intervals(&seq, DEFAULT_DUST_WINDOW, DEFAULT_DUST_THRESHOLD);
+        assert_eq!(intervals.len(), 2, "{intervals:?}");
+        // each run is masked, give or take a base or two of its flanks
+        let near = |(start, end): (usize, usize), (s, e): (usize, usize)| {
+            start.abs_diff(s) <= 3 && end.abs_diff(e) <= 3
+        };
+        assert!(near(intervals[0], (a, a + 30)), "{intervals:?}");
+        assert!(near(intervals[1], (ca, ca + 40)), "{intervals:?}");
+
+        assert!(dust_intervals(MIXED, 64, 20).is_empty());
+        assert_eq!(dust_intervals(b"AAAAAAAAAANNNNNNNNNN", 64, 20), [(0, 10)]);
+        assert!(dust_intervals(b"", 64, 20).is_empty());
+    }
+}
@@ -0,0 +1,685 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, ensure, Result};
+
+use crate::complexity::{DEFAULT_DUST_THRESHOLD, DEFAULT_DUST_WINDOW};
+use crate::islands::IslandCriteria;
+use crate::kmer_maps::{self, KmerMap, DEFAULT_KMER_SIZES};
+use crate::microsatellites::DEFAULT_STR_MIN_COPIES;
+use crate::motifs::{self, Motif};
+use crate::outpath::Format;
+use crate::region;
+use crate::sort::SortOrder;
+use crate::telomeres::TelomereMotif;
+use crate::tracks::{TrackFormat, TrackStat};
+
+/// The depth of the context tree unless told otherwise.
+pub const DEFAULT_CTW_DEPTH: usize = 6;
+
+/// What to compute in each window, and what to write.
+///
+/// Made with [`Config::builder`], which checks the settings fit together,
+/// or from the command line with [`Config::from_args`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub window_size: usize,
+    // a window starts every `step` bases
+    pub step: usize,
+    // only count uppercase bases
+    pub masked: bool,
+    // compute the context-tree weighting, with a tree this deep
+    pub ctw: bool,
+    pub ctw_depth: usize,
+    // the k-mers counted in each window, shortest first
+    pub kmer_maps: Vec<KmerMap>,
+    // the dinucleotides to give Karlin's rho for, symmetrised if canonical
+    pub rho: Option<KmerMap>,
+    // the motifs counted in each window, and whether matches can overlap
+    pub motifs: Vec<Motif>,
+    pub overlapping_motifs: bool,
+    // find the dominant tandem repeat of each window
+    pub tandem_repeats: bool,
+    // add a column for the fasta header descriptions
+    pub description: bool,
+    // the format of the window statistics and k-mer count files
+    pub format: Format,
+    // statistics also written as genome browser tracks, in each format
+    pub tracks: Vec<TrackStat>,
+    pub track_formats: Vec<TrackFormat>,
+    // write sequences in this order rather than the input's
+    pub sort: Option<SortOrder>,
+    // write the runs of Ns at least `min_gap` long as a BED, with their
+    // counts in each window, and as an AGP
+    pub gaps: bool,
+    pub min_gap: usize,
+    pub agp: bool,
+    // write the soft-masked runs as a BED, with statistics of them in
+    // each window
+    pub masked_runs: bool,
+    // describe the homopolymers and short tandem repeats in each window,
+    // counting homopolymers at least `min_homopolymer` long, and write the
+    // repeats as a BED. A repeat of an n bp unit needs at least
+    // `str_min_copies[n - 1]` copies
+    pub strs: bool,
+    pub str_bed: bool,
+    pub min_homopolymer: usize,
+    pub str_min_copies: [usize; 6],
+    // add the DUST score and Wootton-Federhen complexity of each window,
+    // and write the intervals DUST masks, with its window and threshold
+    pub complexity: bool,
+    pub dust_bed: bool,
+    pub dust_window: usize,
+    pub dust_threshold: u32,
+    // summarise each whole sequence and the genome
+    pub summary: bool,
+    // what counts as a CpG island, in CpG island mode
+    pub islands: IslandCriteria,
+    // the repeat counted in telomere mode
+    pub telomere_motif: TelomereMotif,
+    // show a progress bar on stderr
+    pub progress: bool,
+}
+
+impl Config {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// The settings given on the command line.
+    pub fn from_args(matches: &clap::ArgMatches) -> Result<Self> {
+        let window_size = *matches
+            .get_one::<usize>("window_size")
+            .expect("defaulted by clap");
+        let mut builder = Config::builder()
+            .window_size(window_size)
+            // tiles by default, overlapping windows if step < window size
+            .step(
+                matches
+                    .get_one::<usize>("step")
+                    .copied()
+                    .unwrap_or(window_size),
+            )
+            .masked(matches.get_flag("masked"))
+            .ctw(matches.get_flag("ctw"))
+            .kmer_sizes(
+                &matches
+                    .get_many::<usize>("kmer_sizes")
+                    .expect("defaulted by clap")
+                    .copied()
+                    .collect::<Vec<_>>(),
+            )
+            .canonical(matches.get_flag("canonical"))
+            .rho(matches.get_flag("rho"))
+            .overlapping_motifs(matches.get_flag("overlapping_motifs"))
+            .tandem_repeats(matches.get_flag("tandem_repeats"))
+            .description(matches.get_flag("description"))
+            .format(
+                matches
+                    .get_one::<String>("format")
+                    .expect("defaulted by clap")
+                    .parse()?,
+            )
+            .tracks(matches.get_many::<String>("tracks").unwrap_or_default())
+            .track_formats(
+                matches
+                    .get_many::<String>("track_format")
+                    .expect("defaulted by clap")
+                    .map(|f| f.parse())
+                    .collect::<Result<Vec<_>>>()?,
+            )
+            .gaps(matches.get_flag("gaps"))
+            .min_gap(
+                *matches
+                    .get_one::<usize>("min_gap")
+                    .expect("defaulted by clap"),
+            )
+            .agp(matches.get_flag("agp"))
+            .masked_runs(matches.get_flag("masked_runs"))
+            .strs(matches.get_flag("strs"))
+            .str_bed(matches.get_flag("str_bed"))
+            .min_homopolymer(
+                *matches
+                    .get_one::<usize>("min_homopolymer")
+                    .expect("defaulted by clap"),
+            )
+            .str_min_copies(
+                &matches
+                    .get_many::<usize>("str_min_copies")
+                    .expect("defaulted by clap")
+                    .copied()
+                    .collect::<Vec<_>>(),
+            )
+            .complexity(matches.get_flag("complexity"))
+            .dust_bed(matches.get_flag("dust_bed"))
+            .dust_window(
+                *matches
+                    .get_one::<usize>("dust_window")
+                    .expect("defaulted by clap"),
+            )
+            .dust_threshold(
+                *matches
+                    .get_one::<u32>("dust_threshold")
+                    .expect("defaulted by clap"),
+            )
+            .summary(matches.get_flag("summary"))
+            .islands(IslandCriteria {
+                min_length: *matches
+                    .get_one::<usize>("island_min_length")
+                    .expect("defaulted by clap"),
+                min_gc: *matches
+                    .get_one::<f32>("island_min_gc")
+                    .expect("defaulted by clap"),
+                min_oe: *matches
+                    .get_one::<f32>("island_min_oe")
+                    .expect("defaulted by clap"),
+                merge_gap: *matches
+                    .get_one::<usize>("island_merge_gap")
+                    .expect("defaulted by clap"),
+            })
+            .telomere_motif(
+                matches
+                    .get_one::<String>("telomere_motif")
+                    .expect("defaulted by clap")
+                    .parse()?,
+            )
+            .progress(true);
+        if let Some(path) = matches.get_one::<PathBuf>("motifs") {
+            builder = builder.motifs(motifs::read_motifs(path)?);
+        }
+        if let Some(sort) = matches.get_one::<String>("sort") {
+            builder = builder.sort(sort.parse()?);
+        }
+        let config = builder.build()?;
+        // checked now rather than when the bigWig is written, after every
+        // window has been computed
+        if !config.tracks.is_empty() && config.track_formats.contains(&TrackFormat::BigWig) {
+            region::check_in_order(&region::from_args(matches)?)?;
+        }
+        Ok(config)
+    }
+
+    /// The suffixes of the output files, in the order their sinks are
+    /// passed to [`fasta_windows`](crate::fw::fasta_windows): the windows,
+    /// the mononucleotides, each k-mer size, rho, the motifs, the tandem
+    /// repeats, each track in each track format, the gaps BED and AGP, the
+    /// masked runs BED, the STR BED, the DUST BED, then the summary TSV and
+    /// JSON.
+    pub fn output_suffixes(&self) -> Vec<String> {
+        let mut stems = vec!["freq_windows".to_string(), "mononuc_windows".to_string()];
+        stems.extend(self.kmer_maps.iter().map(|m| m.file_stem()));
+        if self.rho.is_some() {
+            stems.push("rho_windows".to_string());
+        }
+        if !self.motifs.is_empty() {
+            stems.push("motif_windows".to_string());
+        }
+        if self.tandem_repeats {
+            stems.push("tandem_windows".to_string());
+        }
+        let mut suffixes: Vec<String> = stems
+            .iter()
+            .map(|stem| format!("{stem}.{}", self.format.extension()))
+            .collect();
+        for stat in &self.tracks {
+            for track_format in &self.track_formats {
+                suffixes.push(format!("{}.{}", stat.name, track_format.extension()));
+            }
+        }
+        if self.gaps {
+            suffixes.push("gaps.bed".to_string());
+        }
+        if self.agp {
+            suffixes.push("assembly.agp".to_string());
+        }
+        if self.masked_runs {
+            suffixes.push("masked.bed".to_string());
+        }
+        if self.str_bed {
+            suffixes.push("strs.bed".to_string());
+        }
+        if self.dust_bed {
+            suffixes.push("dust.bed".to_string());
+        }
+        if self.summary {
+            suffixes.extend(["summary.tsv".to_string(), "summary.json".to_string()]);
+        }
+        suffixes
+    }
+
+    /// The number of window statistics, k-mer count, rho, motif and tandem
+    /// repeat tables, which come before the tracks in
+    /// [`output_suffixes`](Self::output_suffixes).
+    pub fn table_count(&self) -> usize {
+        2 + self.kmer_maps.len()
+            + self.rho.iter().count()
+            + !self.motifs.is_empty() as usize
+            + self.tandem_repeats as usize
+    }
+}
+
+/// Builds a [`Config`], starting from the command line defaults: 1kb
+/// tiles, no CTW, and di-, tri- and tetranucleotides written as TSV.
+#[derive(Debug, Clone)]
+pub struct ConfigBuilder {
+    window_size: usize,
+    step: Option<usize>,
+    masked: bool,
+    ctw: bool,
+    ctw_depth: usize,
+    kmer_sizes: Vec<usize>,
+    canonical: bool,
+    rho: bool,
+    motifs: Vec<Motif>,
+    overlapping_motifs: bool,
+    tandem_repeats: bool,
+    description: bool,
+    format: Format,
+    tracks: Vec<String>,
+    track_formats: Vec<TrackFormat>,
+    sort: Option<SortOrder>,
+    gaps: bool,
+    min_gap: usize,
+    agp: bool,
+    masked_runs: bool,
+    strs: bool,
+    str_bed: bool,
+    min_homopolymer: usize,
+    str_min_copies: Vec<usize>,
+    complexity: bool,
+    dust_bed: bool,
+    dust_window: usize,
+    dust_threshold: u32,
+    summary: bool,
+    islands: IslandCriteria,
+    telomere_motif: TelomereMotif,
+    progress: bool,
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        Self {
+            window_size: 1000,
+            step: None,
+            masked: false,
+            ctw: false,
+            ctw_depth: DEFAULT_CTW_DEPTH,
+            kmer_sizes: DEFAULT_KMER_SIZES.to_vec(),
+            canonical: false,
+            rho: false,
+            motifs: Vec::new(),
+            overlapping_motifs: false,
+            tandem_repeats: false,
+            description: false,
+            format: Format::Tsv,
+            tracks: Vec::new(),
+            track_formats: vec![TrackFormat::BedGraph],
+            sort: None,
+            gaps: false,
+            min_gap: 1,
+            agp: false,
+            masked_runs: false,
+            strs: false,
+            str_bed: false,
+            min_homopolymer: 5,
+            str_min_copies: DEFAULT_STR_MIN_COPIES.to_vec(),
+            complexity: false,
+            dust_bed: false,
+            dust_window: DEFAULT_DUST_WINDOW,
+            dust_threshold: DEFAULT_DUST_THRESHOLD,
+            summary: false,
+            islands: IslandCriteria::default(),
+            telomere_motif: TelomereMotif::default(),
+            progress: false,
+        }
+    }
+}
+
+impl ConfigBuilder {
+    pub fn window_size(mut self, window_size: usize) -> Self {
+        self.window_size = window_size;
+        self
+    }
+
+    /// Start a window every `step` bases. Defaults to the window size.
+    pub fn step(mut self, step: usize) -> Self {
+        self.step = Some(step);
+        self
+    }
+
+    pub fn masked(mut self, masked: bool) -> Self {
+        self.masked = masked;
+        self
+    }
+
+    pub fn ctw(mut self, ctw: bool) -> Self {
+        self.ctw = ctw;
+        self
+    }
+
+    pub fn ctw_depth(mut self, depth: usize) -> Self {
+        self.ctw_depth = depth;
+        self
+    }
+
+    pub fn kmer_sizes(mut self, sizes: &[usize]) -> Self {
+        self.kmer_sizes = sizes.to_vec();
+        self
+    }
+
+    pub fn canonical(mut self, canonical: bool) -> Self {
+        self.canonical = canonical;
+        self
+    }
+
+    /// Also write Karlin's dinucleotide relative abundances.
+    pub fn rho(mut self, rho: bool) -> Self {
+        self.rho = rho;
+        self
+    }
+
+    /// Motifs to count in each window.
+    pub fn motifs(mut self, motifs: Vec<Motif>) -> Self {
+        self.motifs = motifs;
+        self
+    }
+
+    /// Count every match of each motif, even those overlapping the one
+    /// before.
+    pub fn overlapping_motifs(mut self, overlapping: bool) -> Self {
+        self.overlapping_motifs = overlapping;
+        self
+    }
+
+    /// Also find the dominant tandem repeat of each window.
+    pub fn tandem_repeats(mut self, tandem_repeats: bool) -> Self {
+        self.tandem_repeats = tandem_repeats;
+        self
+    }
+
+    pub fn description(mut self, description: bool) -> Self {
+        self.description = description;
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Statistics to also write as tracks, named as their column in the
+    /// windows file.
+    pub fn tracks<S: AsRef<str>>(mut self, stats: impl IntoIterator<Item = S>) -> Self {
+        self.tracks = stats.into_iter().map(|s| s.as_ref().to_string()).collect();
+        self
+    }
+
+    pub fn track_formats(mut self, formats: Vec<TrackFormat>) -> Self {
+        self.track_formats = formats;
+        self
+    }
+
+    pub fn sort(mut self, sort: SortOrder) -> Self {
+        self.sort = Some(sort);
+        self
+    }
+
+    /// Also write the gaps as a BED, and count them in each window.
+    pub fn gaps(mut self, gaps: bool) -> Self {
+        self.gaps = gaps;
+        self
+    }
+
+    /// Only runs of at least this many Ns are gaps.
+    pub fn min_gap(mut self, min_gap: usize) -> Self {
+        self.min_gap = min_gap;
+        self
+    }
+
+    /// Also describe each sequence as contigs and gaps in an AGP.
+    pub fn agp(mut self, agp: bool) -> Self {
+        self.agp = agp;
+        self
+    }
+
+    /// Also write the soft-masked runs as a BED, and describe them in
+    /// each window.
+    pub fn masked_runs(mut self, masked_runs: bool) -> Self {
+        self.masked_runs = masked_runs;
+        self
+    }
+
+    /// Also describe the homopolymers and short tandem repeats in each
+    /// window.
+    pub fn strs(mut self, strs: bool) -> Self {
+        self.strs = strs;
+        self
+    }
+
+    /// Also write the short tandem repeats as a BED.
+    pub fn str_bed(mut self, str_bed: bool) -> Self {
+        self.str_bed = str_bed;
+        self
+    }
+
+    /// Only homopolymers at least this long are counted in each window.
+    pub fn min_homopolymer(mut self, min_homopolymer: usize) -> Self {
+        self.min_homopolymer = min_homopolymer;
+        self
+    }
+
+    /// The fewest copies of a 1 to 6bp unit that make a short tandem
+    /// repeat, for each unit length in turn.
+    pub fn str_min_copies(mut self, min_copies: &[usize]) -> Self {
+        self.str_min_copies = min_copies.to_vec();
+        self
+    }
+
+    /// Also score the complexity of each window by DUST and by
+    /// Wootton-Federhen.
+    pub fn complexity(mut self, complexity: bool) -> Self {
+        self.complexity = complexity;
+        self
+    }
+
+    /// Also write the intervals masked by DUST as a BED.
+    pub fn dust_bed(mut self, dust_bed: bool) -> Self {
+        self.dust_bed = dust_bed;
+        self
+    }
+
+    /// DUST masks intervals of at most this many bases.
+    pub fn dust_window(mut self, window: usize) -> Self {
+        self.dust_window = window;
+        self
+    }
+
+    /// DUST masks intervals scoring above this, in tenths.
+    pub fn dust_threshold(mut self, threshold: u32) -> Self {
+        self.dust_threshold = threshold;
+        self
+    }
+
+    /// Also summarise each whole sequence, and the genome.
+    pub fn summary(mut self, summary: bool) -> Self {
+        self.summary = summary;
+        self
+    }
+
+    pub fn islands(mut self, criteria: IslandCriteria) -> Self {
+        self.islands = criteria;
+        self
+    }
+
+    pub fn telomere_motif(mut self, motif: TelomereMotif) -> Self {
+        self.telomere_motif = motif;
+        self
+    }
+
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn build(self) -> Result<Config> {
+        let step = self.step.unwrap_or(self.window_size);
+        ensure!(
+            self.window_size > 0,
+            "window size must be greater than zero"
+        );
+        ensure!(step > 0, "step must be greater than zero");
+        ensure!(self.ctw_depth > 0, "CTW depth must be greater than zero");
+        ensure!(
+            self.min_gap > 0,
+            "the minimum gap must be greater than zero"
+        );
+        ensure!(
+            self.min_homopolymer > 0,
+            "the minimum homopolymer must be greater than zero"
+        );
+        let str_min_copies: [usize; 6] = self.str_min_copies[..].try_into().map_err(|_| {
+            anyhow!(
+                "expected 6 minimum STR copy numbers, one per unit length, not {}",
+                self.str_min_copies.len()
+            )
+        })?;
+        ensure!(
+            str_min_copies.iter().all(|&copies| copies >= 2),
+            "a short tandem repeat needs at least 2 copies of its unit"
+        );
+        ensure!(
+            self.dust_window >= 4,
+            "the DUST window must be at least 4 bases"
+        );
+        self.islands.check()?;
+        let kmer_maps = kmer_maps::generate_kmer_maps(&self.kmer_sizes, self.canonical)?;
+        let tracks = self
+            .tracks
+            .iter()
+            .map(|name| TrackStat::parse(name, &kmer_maps, self.ctw))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Config {
+            window_size: self.window_size,
+            step,
+            masked: self.masked,
+            ctw: self.ctw,
+            ctw_depth: self.ctw_depth,
+            kmer_maps,
+            rho: self.rho.then_some(KmerMap {
+                len: 2,
+                canonical: self.canonical,
+            }),
+            motifs: self.motifs,
+            overlapping_motifs: self.overlapping_motifs,
+            tandem_repeats: self.tandem_repeats,
+            description: self.description,
+            format: self.format,
+            tracks,
+            track_formats: self.track_formats,
+            sort: self.sort,
+            gaps: self.gaps,
+            min_gap: self.min_gap,
+            agp: self.agp,
+            masked_runs: self.masked_runs,
+            strs: self.strs,
+            str_bed: self.str_bed,
+            min_homopolymer: self.min_homopolymer,
+            str_min_copies,
+            complexity: self.complexity,
+            dust_bed: self.dust_bed,
+            dust_window: self.dust_window,
+            dust_threshold: self.dust_threshold,
+            summary: self.summary,
+            islands: self.islands,
+            telomere_motif: self.telomere_motif,
+            progress: self.progress,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let config = Config::builder().window_size(500).build().unwrap();
+        assert_eq!(config.step, 500);
+        assert_eq!(config.kmer_maps.len(), 3);
+        assert_eq!(config.output_suffixes().len(), config.table_count());
+
+        let config = Config::builder()
+            .kmer_sizes(&[5])
+            .format(Format::Parquet)
+            .tracks(["GC_prop", "5mer_Shannon"])
+            .track_formats(vec![TrackFormat::BedGraph, TrackFormat::BigWig])
+            .build()
+            .unwrap();
+        assert_eq!(
+            config.output_suffixes(),
+            [
+                "freq_windows.parquet",
+                "mononuc_windows.parquet",
+                "5mer_windows.parquet",
+                "GC_prop.bedGraph",
+                "GC_prop.bw",
+                "5mer_Shannon.bedGraph",
+                "5mer_Shannon.bw",
+            ]
+        );
+
+        let config = Config::builder().summary(true).build().unwrap();
+        assert_eq!(
+            config.output_suffixes()[config.table_count()..],
+            ["summary.tsv", "summary.json"]
+        );
+
+        let config = Config::builder()
+            .gaps(true)
+            .agp(true)
+            .masked_runs(true)
+            .str_bed(true)
+            .dust_bed(true)
+            .summary(true)
+            .build()
+            .unwrap();
+        assert_eq!(
+            config.output_suffixes()[config.table_count()..],
+            [
+                "gaps.bed",
+                "assembly.agp",
+                "masked.bed",
+                "strs.bed",
+                "dust.bed",
+                "summary.tsv",
+                "summary.json"
+            ]
+        );
+
+        let config = Config::builder().rho(true).canonical(true).build().unwrap();
+        assert_eq!(config.output_suffixes()[5], "rho_windows.tsv");
+        assert_eq!(config.rho.unwrap().kmers().len(), 10);
+
+        let motifs = vec![Motif::new("DpnII", "GATC").unwrap()];
+        let config = Config::builder().rho(true).motifs(motifs).build().unwrap();
+        assert_eq!(config.table_count(), 7);
+        assert_eq!(config.output_suffixes()[6], "motif_windows.tsv");
+        let config = Config::builder().tandem_repeats(true).build().unwrap();
+        assert_eq!(config.output_suffixes()[5], "tandem_windows.tsv");
+
+        assert!(Config::builder().step(0).build().is_err());
+        assert!(Config::builder().str_min_copies(&[5]).build().is_err());
+        assert!(Config::builder().dust_window(3).build().is_err());
+        assert!(Config::builder()
+            .str_min_copies(&[12, 7, 5, 4, 4, 1])
+            .build()
+            .is_err());
+        let islands = IslandCriteria {
+            min_gc: 50.0,
+            ..Default::default()
+        };
+        assert!(Config::builder().islands(islands).build().is_err());
+        assert!(Config::builder().kmer_sizes(&[40]).build().is_err());
+        assert!(Config::builder().tracks(["ctw"]).build().is_err());
+        assert!(Config::builder().ctw(true).tracks(["ctw"]).build().is_ok());
+    }
+}
@@ -1,12 +1,18 @@
 use std::io::prelude::*;
-use std::path::PathBuf;
-use std::{fs::File, io::BufWriter};
 
 use anyhow::Result;
-use indicatif::{ProgressBar, ProgressStyle};
-use needletail::parse_fastx_file;
 use rayon::prelude::*;
 
+use crate::complexity::{acgt_counts, dust_intervals, dust_score, wootton_federhen};
+use crate::config::Config;
+use crate::error::check_sequence;
+use crate::input::Input;
+use crate::pipeline::{process_in_order, InFlight};
+use crate::progress::Progress;
+use crate::reader::FastaRecord;
+use crate::seq_statsu8::ByteCounts;
+use crate::windows::SlidingWindows;
+
 // Bins each byte into one of 6 classes: A=0 C=1 G=2 T=3 N=4 other=5.
 // Lowercase acgtn are folded to their uppercase equivalents.
 const NUC_LUT: [u8; 256] = build_nuc_lut();
@@ -76,85 +82,137 @@ pub fn entropy_fast(dna: &[u8], masked: bool) -> f64 {
 /// Fast path that only computes Shannon entropy and writes a BED file.
 ///
 /// Architecture:
-///   1. Read all records sequentially into memory (bio strips newlines for free).
-///   2. Compute entropy in parallel with rayon's indexed par_iter + par_chunks:
-///      - par_iter across chromosomes keeps all cores busy for many-scaffold genomes.
-///      - par_chunks within each chromosome fully utilises cores for few-scaffold genomes.
-///      - rayon's IndexedParallelIterator collect() preserves FASTA file order,
-///        so no sort or MPSC channel is needed.
-///   3. Write in order directly.
-pub fn entropy_windows(matches: &clap::ArgMatches, mut bed_file: BufWriter<File>) -> Result<()> {
-    let input_fasta = matches
-        .get_one::<PathBuf>("fasta")
-        .expect("handled by clap");
-    let output = matches
-        .get_one::<PathBuf>("output")
-        .expect("handled by clap");
-    let window_size = matches.get_one::<usize>("window_size").cloned().unwrap();
-    let masked = matches.get_one::<bool>("masked").cloned().unwrap();
-
-    let spinner = ProgressBar::new_spinner();
-    spinner.set_style(ProgressStyle::with_template(
-        "[+]\tReading: {spinner:.cyan} {pos} sequences",
-    )?);
-
-    eprintln!("[+]\tReading fasta (entropy mode)");
-    // needletail's seq() strips newlines with SIMD memchr2 and returns Cow::Owned
-    // for multi-line sequences (the common case), so into_owned() is a move not a copy.
-    let mut records: Vec<(String, Vec<u8>)> = Vec::new();
-    let mut reader = parse_fastx_file(input_fasta)?;
-    while let Some(result) = reader.next() {
-        let rec = result?;
-        let raw_id = rec.id();
-        let id_end = raw_id
-            .iter()
-            .position(|&b| b == b' ' || b == b'\t')
-            .unwrap_or(raw_id.len());
-        let id = String::from_utf8_lossy(&raw_id[..id_end]).into_owned();
-        let seq = rec.seq().into_owned();
-        records.push((id, seq));
-        spinner.inc(1);
+///   1. Stream records from the fasta (needletail strips newlines for free).
+///   2. Compute entropy in parallel with rayon, twice over:
+///      - records are processed concurrently as they are read, which keeps all
+///        cores busy for many-scaffold genomes.
+///      - par_iter across windows within each chromosome fully utilises cores for
+///        few-scaffold genomes, whether windows are tiled or overlapping (`--step`).
+///   3. Write each record as soon as it and every record before it is done, so
+///      the BED is in FASTA file order and only a few records are held in memory.
+///
+/// The BED is written to `bed`, which can be any writer. With `--complexity`
+/// it also has the DUST score and Wootton-Federhen complexity of each window,
+/// and the intervals DUST masks are written to `dust`, if given. Only the
+/// window size, step, masking, CTW depth, sort order and DUST settings of
+/// `config` are used.
+pub fn entropy_windows<W: Write>(
+    config: &Config,
+    input: Input,
+    mut bed: W,
+    mut dust: Option<W>,
+) -> Result<()> {
+    let (window_size, step, masked) = (config.window_size, config.step, config.masked);
+
+    let mut progress = match config.progress {
+        true => Some(Progress::new(&input)?),
+        false => None,
+    };
+    if config.progress {
+        eprintln!("[+]\tReading fasta (entropy mode)");
     }
-    spinner.finish();
-
-    // par_iter on a Vec is an IndexedParallelIterator: collect() preserves order.
-    // par_chunks within each record gives intra-chromosome parallelism so a
-    // genome dominated by a handful of large scaffolds still uses all cores.
-    let results: Vec<Vec<(usize, usize, f64, f64)>> = records
-        .par_iter()
-        .map(|(_, seq)| {
-            seq.par_chunks(window_size)
-                .enumerate()
-                .map(|(i, win)| {
-                    let start = i * window_size;
-                    let entropy = entropy_fast(win, masked);
-                    let ctw = crate::kmeru8::ctw_bits_per_base_dna(win, 6);
-                    (start, start + win.len(), entropy, ctw)
-                })
+    // Each window is computed independently (CTW has to be rebuilt per
+    // window anyway), so overlapping windows parallelise the same way.
+    // par_iter on a Range is an IndexedParallelIterator: collect() preserves order.
+    let dust_bed = dust.is_some();
+    let process = |record: FastaRecord| -> Result<SequenceEntropy> {
+        let seq = record.seq;
+        let offset = record.offset;
+        check_sequence(&record.id, &seq, offset)?;
+        let windows = SlidingWindows::new(seq.len(), window_size, step)?;
+        let results = (0..windows.count())
+            .into_par_iter()
+            .map(|i| {
+                let (start, end) = windows.get(i);
+                let win = &seq[start..end];
+                let entropy = entropy_fast(win, masked);
+                let ctw = crate::kmeru8::ctw_bits_per_base_dna(win, config.ctw_depth);
+                let complexity = config.complexity.then(|| {
+                    let mut counts = ByteCounts::new();
+                    win.iter().for_each(|&b| counts.add(b));
+                    let acgt = acgt_counts(&counts, masked);
+                    (dust_score(win), wootton_federhen(&acgt))
+                });
+                (offset + start, offset + end, entropy, ctw, complexity)
+            })
+            .collect();
+        let masked = dust_bed.then(|| {
+            dust_intervals(&seq, config.dust_window, config.dust_threshold)
+                .into_iter()
+                .map(|(start, end)| (offset + start, offset + end))
                 .collect()
-        })
-        .collect();
-
-    eprintln!("[+]\tWriting BED output");
-    for ((id, _), windows) in records.iter().zip(results.iter()) {
-        for &(start, end, entropy, ctw) in windows {
-            writeln!(
-                bed_file,
+        });
+        Ok((record.id, seq.len(), results, masked))
+    };
+
+    let mut write = |(id, _, windows, masked): &SequenceEntropy| -> Result<()> {
+        for &(start, end, entropy, ctw, complexity) in windows {
+            write!(
+                bed,
                 "{}\t{}\t{}\t{:.6}\t{:.6}",
                 id, start, end, entropy, ctw
             )?;
+            if let Some((dust, wf)) = complexity {
+                write!(bed, "\t{dust:.6}\t{wf:.6}")?;
+            }
+            writeln!(bed)?;
+        }
+        if let (Some(file), Some(masked)) = (&mut dust, masked) {
+            for &(start, end) in masked {
+                writeln!(file, "{id}\t{start}\t{end}\t{}", end - start)?;
+            }
         }
+        Ok(())
+    };
+
+    // sorting needs every record before the first can be written.
+    let mut sorted = Vec::new();
+    process_in_order(
+        input,
+        InFlight::default(),
+        |record| record.seq.len(),
+        process,
+        |result| {
+            if let Some(progress) = &mut progress {
+                progress.inc(result.1);
+            }
+            match config.sort {
+                Some(_) => sorted.push(result),
+                None => write(&result)?,
+            }
+            Ok(())
+        },
+    )?;
+    if let Some(progress) = progress {
+        progress.finish();
     }
-    bed_file.flush()?;
 
-    eprintln!(
-        "[+]\tOutput written to: ./fw_out/{}_entropy.bed",
-        output.display()
-    );
+    if let Some(order) = config.sort {
+        sorted.sort_by(|(a, a_len, ..), (b, b_len, ..)| order.compare((a, *a_len), (b, *b_len)));
+        for result in &sorted {
+            write(result)?;
+        }
+    }
+    bed.flush()?;
+    if let Some(file) = &mut dust {
+        file.flush()?;
+    }
 
     Ok(())
 }
 
+// start, end, shannon entropy and CTW bits per base of a window, and its
+// DUST score and Wootton-Federhen complexity if asked for
+type EntropyWindow = (usize, usize, f64, f64, Option<(f32, f32)>);
+
+// the id, length, windows and DUST-masked intervals of a sequence
+type SequenceEntropy = (
+    String,
+    usize,
+    Vec<EntropyWindow>,
+    Option<Vec<(usize, usize)>>,
+);
+
 #[cfg(test)]
 mod tests {
     use super::entropy_fast;
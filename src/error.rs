@@ -0,0 +1,125 @@
+use std::io;
+
+use needletail::errors::{ParseError, ParseErrorKind};
+use thiserror::Error;
+
+/// What can be wrong with the input fasta.
+///
+/// These come back inside an `anyhow::Error`, from which they can be
+/// recovered with `downcast_ref::<FastaError>()`.
+#[derive(Debug, Error)]
+pub enum FastaError {
+    /// The input couldn't be read at all, or stopped part way through.
+    #[error("could not read {input}")]
+    Io {
+        input: String,
+        #[source]
+        source: io::Error,
+    },
+    /// The input isn't fasta, or a record is cut short.
+    #[error("{input}{}: {message}", at(*line, record.as_deref()))]
+    Parse {
+        input: String,
+        // the record being read, if it got as far as the header
+        record: Option<String>,
+        // 1-based, or 0 if not known
+        line: u64,
+        message: String,
+    },
+    /// A sequence holds something that isn't a letter, a gap or a stop.
+    #[error("invalid character {byte:?} in {record} at position {position}")]
+    InvalidCharacter {
+        record: String,
+        // 1-based, on the whole sequence
+        position: usize,
+        byte: char,
+    },
+    /// The input has no records.
+    #[error("{input} has no sequences in it")]
+    EmptyInput { input: String },
+}
+
+impl FastaError {
+    /// The error for what needletail couldn't parse in `input`.
+    pub fn from_parse(input: &str, error: ParseError) -> Self {
+        let input = input.to_string();
+        let message = match error.kind {
+            ParseErrorKind::EmptyFile => return FastaError::EmptyInput { input },
+            ParseErrorKind::Io => {
+                return FastaError::Io {
+                    input,
+                    source: io::Error::other(error.msg),
+                }
+            }
+            ParseErrorKind::UnexpectedEnd => "the input ends part way through a record".to_string(),
+            _ => error.msg,
+        };
+        FastaError::Parse {
+            input,
+            record: error.position.id,
+            line: error.position.line,
+            message,
+        }
+    }
+}
+
+// where a parse error happened, as far as is known
+fn at(line: u64, record: Option<&str>) -> String {
+    match (line, record) {
+        (0, _) => String::new(),
+        (line, None) => format!(", line {line}"),
+        (line, Some(record)) => format!(", line {line} (record {record})"),
+    }
+}
+
+// the bytes a sequence may hold: letters for bases (IUPAC codes, and
+// amino acids), and gaps and stops
+const VALID: [bool; 256] = build_valid();
+const fn build_valid() -> [bool; 256] {
+    let mut lut = [false; 256];
+    let mut b = 0;
+    while b < 256 {
+        lut[b] = (b as u8).is_ascii_alphabetic() || matches!(b as u8, b'-' | b'.' | b'*');
+        b += 1;
+    }
+    lut
+}
+
+/// Check a sequence holds nothing but valid characters. `offset` is where
+/// it starts on the whole sequence, for the position in the error.
+pub fn check_sequence(record: &str, seq: &[u8], offset: usize) -> Result<(), FastaError> {
+    match seq.iter().position(|&b| !VALID[b as usize]) {
+        None => Ok(()),
+        Some(i) => Err(FastaError::InvalidCharacter {
+            record: record.to_string(),
+            position: offset + i + 1,
+            byte: seq[i] as char,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        assert!(check_sequence("chr1", b"ACGTNRYacgt-*", 0).is_ok());
+        let err = check_sequence("chr1", b"ACG T", 100).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid character ' ' in chr1 at position 104"
+        );
+
+        let parse = FastaError::Parse {
+            input: "x.fa".to_string(),
+            record: Some("chr2".to_string()),
+            line: 3,
+            message: "the input ends part way through a record".to_string(),
+        };
+        assert_eq!(
+            parse.to_string(),
+            "x.fa, line 3 (record chr2): the input ends part way through a record"
+        );
+    }
+}
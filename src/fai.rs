@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+
+/// One line of a samtools `.fai` index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaiEntry {
+    pub name: String,
+    // the number of bases in the sequence
+    pub len: usize,
+    // the byte offset of the first base
+    pub offset: u64,
+    // bases on each full line
+    pub line_bases: usize,
+    // bytes on each full line, including the newline
+    pub line_width: usize,
+}
+
+impl FaiEntry {
+    /// The entry for a sequence whose first base is at byte `offset`, from
+    /// its lines as they are in the file.
+    ///
+    /// Like samtools, every line but the last has to be the same length.
+    pub fn from_lines(name: String, offset: u64, raw_seq: &[u8]) -> Result<Self> {
+        let mut lines: Vec<&[u8]> = raw_seq.split(|&b| b == b'\n').collect();
+        // blank lines before the next header don't count
+        while lines.len() > 1 && lines.last().is_some_and(|l| l.is_empty() || l == b"\r") {
+            lines.pop();
+        }
+        let first = lines[0];
+        let line_width = first.len() + 1;
+        let line_bases = first.strip_suffix(b"\r").unwrap_or(first).len();
+        let mut len = 0;
+        for (i, line) in lines.iter().enumerate() {
+            let bases = line.strip_suffix(b"\r").unwrap_or(line).len();
+            let last = i + 1 == lines.len();
+            ensure!(
+                (last && bases <= line_bases) || line.len() + 1 == line_width,
+                "can't index {name}: its lines are different lengths"
+            );
+            len += bases;
+        }
+        // empty sequences have no lines at all
+        let (line_bases, line_width) = match len {
+            0 => (0, 0),
+            _ => (line_bases, line_width),
+        };
+        Ok(Self {
+            name,
+            len,
+            offset,
+            line_bases,
+            line_width,
+        })
+    }
+
+    /// The byte offset of base `pos` (0-based) in the fasta file.
+    pub fn byte_pos(&self, pos: usize) -> u64 {
+        self.offset + ((pos / self.line_bases) * self.line_width + pos % self.line_bases) as u64
+    }
+
+    /// Read bases `start..end` (0-based, half-open) from the fasta file.
+    pub fn fetch<R: Read + Seek>(
+        &self,
+        fasta: &mut R,
+        start: usize,
+        end: usize,
+    ) -> Result<Vec<u8>> {
+        let end = end.min(self.len);
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        let from = self.byte_pos(start);
+        // the last base is always on the line we stop on
+        let to = self.byte_pos(end - 1) + 1;
+
+        let mut bytes = vec![0; (to - from) as usize];
+        fasta.seek(SeekFrom::Start(from))?;
+        fasta
+            .read_exact(&mut bytes)
+            .with_context(|| format!("{} is shorter than its index says", self.name))?;
+        bytes.retain(|&b| b != b'\n' && b != b'\r');
+        ensure!(
+            bytes.len() == end - start,
+            "the index of {} doesn't match the fasta, is it out of date?",
+            self.name
+        );
+        Ok(bytes)
+    }
+
+    // the byte just past the last base
+    fn seq_end(&self) -> u64 {
+        match self.len {
+            0 => self.offset,
+            len => self.byte_pos(len - 1) + 1,
+        }
+    }
+}
+
+/// A samtools-compatible fasta index, giving random access to the sequences
+/// of an uncompressed fasta file.
+#[derive(Debug, Clone, Default)]
+pub struct Fai {
+    entries: Vec<FaiEntry>,
+    by_name: HashMap<String, usize>,
+}
+
+impl Fai {
+    /// The conventional index path for a fasta file, `<fasta>.fai`.
+    pub fn path_for(fasta: &Path) -> PathBuf {
+        let mut name = OsString::from(fasta.as_os_str());
+        name.push(".fai");
+        PathBuf::from(name)
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+        Self::from_reader(BufReader::new(file))
+            .with_context(|| format!("could not read the index {}", path.display()))
+    }
+
+    pub fn from_reader(reader: impl BufRead) -> Result<Self> {
+        let mut fai = Self::default();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [name, len, offset, line_bases, line_width] = fields[..] else {
+                bail!("line {}: expected 5 tab separated columns", i + 1);
+            };
+            let number = |field: &str| {
+                field
+                    .parse::<usize>()
+                    .with_context(|| format!("line {}: {field:?} is not a number", i + 1))
+            };
+            let entry = FaiEntry {
+                name: name.to_string(),
+                len: number(len)?,
+                offset: number(offset)? as u64,
+                line_bases: number(line_bases)?,
+                line_width: number(line_width)?,
+            };
+            ensure!(
+                entry.len == 0 || (entry.line_bases > 0 && entry.line_width > entry.line_bases),
+                "line {}: bad line lengths for {}",
+                i + 1,
+                entry.name
+            );
+            fai.push(entry);
+        }
+        Ok(fai)
+    }
+
+    fn push(&mut self, entry: FaiEntry) {
+        // like samtools, the first of any duplicate names wins
+        self.by_name
+            .entry(entry.name.clone())
+            .or_insert(self.entries.len());
+        self.entries.push(entry);
+    }
+
+    /// The entries in fasta file order.
+    pub fn entries(&self) -> &[FaiEntry] {
+        &self.entries
+    }
+
+    /// The position of the named sequence in the file, and its entry.
+    pub fn get(&self, name: &str) -> Option<(usize, &FaiEntry)> {
+        let index = *self.by_name.get(name)?;
+        Some((index, &self.entries[index]))
+    }
+
+    /// The header line of the `index`th sequence, without the `>`.
+    ///
+    /// The index doesn't store headers, but each one sits between the end of
+    /// the previous sequence and the start of this one.
+    pub fn header<R: Read + Seek>(&self, fasta: &mut R, index: usize) -> Result<Vec<u8>> {
+        let entry = &self.entries[index];
+        let from = match index {
+            0 => 0,
+            i => self.entries[i - 1].seq_end(),
+        };
+        let mut bytes = vec![0; entry.offset.saturating_sub(from) as usize];
+        fasta.seek(SeekFrom::Start(from))?;
+        fasta.read_exact(&mut bytes)?;
+        let Some(start) = bytes.iter().position(|&b| b == b'>') else {
+            bail!(
+                "no header found for {}, is the index out of date?",
+                entry.name
+            );
+        };
+        let header = &bytes[start + 1..];
+        let end = header
+            .iter()
+            .position(|&b| b == b'\n' || b == b'\r')
+            .unwrap_or(header.len());
+        Ok(header[..end].to_vec())
+    }
+}
+
+/// Writes a `.fai` index next to a fasta file, one entry at a time.
+pub struct FaiWriter {
+    path: PathBuf,
+    out: BufWriter<File>,
+}
+
+impl FaiWriter {
+    /// Start the index of `fasta`, refusing to replace an existing one
+    /// unless `force` is set.
+    pub fn create(fasta: &Path, force: bool) -> Result<Self> {
+        ensure!(
+            fasta != Path::new("-"),
+            "an index can't be written for stdin"
+        );
+        ensure!(
+            crate::reader::is_plain_fasta(fasta)?,
+            "an index can only be written for an uncompressed fasta"
+        );
+        let path = Fai::path_for(fasta);
+        let file = match force {
+            true => File::create(&path),
+            false => OpenOptions::new().write(true).create_new(true).open(&path),
+        };
+        let file = file.with_context(|| match path.exists() && !force {
+            true => format!(
+                "{} already exists, use --force to overwrite it",
+                path.display()
+            ),
+            false => format!("could not create {}", path.display()),
+        })?;
+        Ok(Self {
+            path,
+            out: BufWriter::new(file),
+        })
+    }
+
+    pub fn write(&mut self, entry: &FaiEntry) -> Result<()> {
+        writeln!(
+            self.out,
+            "{}\t{}\t{}\t{}\t{}",
+            entry.name, entry.len, entry.offset, entry.line_bases, entry.line_width
+        )
+        .with_context(|| format!("could not write {}", self.path.display()))
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.out
+            .flush()
+            .with_context(|| format!("could not write {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const FASTA: &[u8] = b">one first\nACGTA\nCGTAC\nGT\n>two\r\nTTTT\r\nGG\r\n";
+    const FAI: &[u8] = b"one\t12\t11\t5\t6\ntwo\t6\t32\t4\t6\n";
+
+    #[test]
+    fn test_fetch() {
+        let fai = Fai::from_reader(FAI).unwrap();
+        let mut fasta = Cursor::new(FASTA);
+
+        let (index, one) = fai.get("one").unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(one.fetch(&mut fasta, 0, 12).unwrap(), b"ACGTACGTACGT");
+        assert_eq!(one.fetch(&mut fasta, 3, 11).unwrap(), b"TACGTACG");
+        assert_eq!(one.fetch(&mut fasta, 5, 10).unwrap(), b"CGTAC");
+        // the end is clamped to the sequence
+        assert_eq!(one.fetch(&mut fasta, 10, 100).unwrap(), b"GT");
+
+        let (_, two) = fai.get("two").unwrap();
+        assert_eq!(two.fetch(&mut fasta, 2, 6).unwrap(), b"TTGG");
+        assert!(fai.get("three").is_none());
+    }
+
+    #[test]
+    fn test_headers() {
+        let fai = Fai::from_reader(FAI).unwrap();
+        let mut fasta = Cursor::new(FASTA);
+        assert_eq!(fai.header(&mut fasta, 0).unwrap(), b"one first");
+        assert_eq!(fai.header(&mut fasta, 1).unwrap(), b"two");
+    }
+
+    #[test]
+    fn test_from_lines() {
+        let one = FaiEntry::from_lines("one".to_string(), 11, b"ACGTA\nCGTAC\nGT").unwrap();
+        let two = FaiEntry::from_lines("two".to_string(), 32, b"TTTT\r\nGG\r\n").unwrap();
+        let fai = Fai::from_reader(FAI).unwrap();
+        assert_eq!(fai.entries(), [one, two]);
+
+        // a single line is fine, but not a long line after short ones
+        assert!(FaiEntry::from_lines("a".to_string(), 0, b"ACGTACGT").is_ok());
+        assert!(FaiEntry::from_lines("a".to_string(), 0, b"ACG\nACGT").is_err());
+        assert!(FaiEntry::from_lines("a".to_string(), 0, b"ACG\nA\nACG").is_err());
+    }
+
+    #[test]
+    fn test_bad_index() {
+        assert!(Fai::from_reader(&b"one\t12\t11\t5\n"[..]).is_err());
+        assert!(Fai::from_reader(&b"one\t12\t11\t5\tfive\n"[..]).is_err());
+    }
+}
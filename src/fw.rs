@@ -1,190 +1,540 @@
-use itertools::Itertools;
 use std::io::prelude::*;
-use std::path::PathBuf;
-use std::{fs::File, io::BufWriter};
-
-use crate::kmer_maps::{self, KmerMap, WriteArray, WriteKmerValues};
-use crate::kmeru8;
+use std::sync::Arc;
+
+use crate::columnar::ColumnarOutput;
+use crate::complexity::{self, Dust};
+use crate::config::Config;
+use crate::error::check_sequence;
+use crate::gaps::{self, Gaps};
+use crate::input::Input;
+use crate::kmer_maps::{self, KmerMap, WriteArray};
+use crate::kmeru8::{self, KmerSpectrum};
+use crate::masked::{self, MaskedRuns};
+use crate::microsatellites::{self, StrLocus, Strs};
+use crate::motifs;
+use crate::outpath::Format;
+use crate::pipeline::{process_in_order, InFlight};
+use crate::progress::Progress;
+use crate::reader::FastaRecord;
 use crate::seq_statsu8;
+use crate::summary::{SequenceTotals, Summary};
+use crate::tandem::{self, TandemRepeat};
+use crate::tracks::Tracks;
+use crate::windows::{slide_range, SlidingWindows};
 
 use anyhow::Result;
-use bio::io::fasta;
-use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
-use std::sync::mpsc::channel;
-
-pub fn fasta_windows(
-    matches: &clap::ArgMatches,
-    mut window_file_0: BufWriter<File>,
-    mut window_file_1: BufWriter<File>,
-    mut window_file_2: BufWriter<File>,
-    mut window_file_3: BufWriter<File>,
-    mut window_file_4: BufWriter<File>,
-) -> Result<()> {
-    // get matches
-    let input_fasta = matches
-        .get_one::<PathBuf>("fasta")
-        .expect("handled by clap");
-    let output = matches
-        .get_one::<PathBuf>("output")
-        .expect("handled by clap");
-    let window_size = matches.get_one::<usize>("window_size").cloned().unwrap();
-    let masked = matches.get_one::<bool>("masked").cloned().unwrap();
-    let description = matches.get_one::<bool>("description").cloned().unwrap();
-    let ctw = matches.get_one::<bool>("ctw").cloned().unwrap();
-
-    // compute the 2-4mer kmer maps once only
-    // hard code with false until I decide how to deal with
-    // canonical kmers
-    let kmer_maps = kmer_maps::generate_kmer_maps(false);
-
-    // channel for collecting output
-    let (sender, receiver) = channel();
-
-    // iterate over fasta to get number of sequences
-    // for the progress bar
-    let mut nb_reads = 0;
-    // read in the fasta from file
-    let mut reader = fasta::Reader::from_file(input_fasta)
-        .expect("[-]\tPath invalid.")
-        .records();
-    while let Some(Ok(_record)) = reader.next() {
-        nb_reads += 1;
+
+/// Compute the statistics of every window of `input` and write them to
+/// `sinks`, one for each of [`Config::output_suffixes`] in that order.
+///
+/// Any writer will do, such as stdout, a pipe or an in-memory buffer,
+/// except for bigWig tracks, which have to seek back to write their index
+/// and so need a [`Sink::Seekable`], such as a file or a `Cursor<Vec<u8>>`.
+pub fn fasta_windows(config: &Config, input: Input, sinks: Vec<Sink<'_>>) -> Result<()> {
+    let mut outputs = outputs(config, sinks)?;
+
+    // each record is processed as soon as it's read, and written as soon
+    // as it and all the records before it are done, so the output is in
+    // the order of the assembly (and any .fai/AGP built from it) and only
+    // a few records are ever held in memory.
+    let mut progress = match config.progress {
+        true => Some(Progress::new(&input)?),
+        false => None,
+    };
+    if config.progress {
+        eprintln!("[+]\tReading fasta from file");
     }
 
-    let progress_bar = ProgressBar::new(nb_reads);
-    let pb_style = ProgressStyle::with_template(
-        "[+]\tProcessing records: {bar:40.cyan/blue} {pos:>7}/{len:12}",
-    )?
-    .progress_chars(">>-");
-    progress_bar.set_style(pb_style);
-
-    // second reader for the computation
-    eprintln!("[+]\tReading fasta from file");
-    let reader = fasta::Reader::from_file(input_fasta).expect("[-]\tPath invalid.");
-    reader
-        .records()
-        .par_bridge()
-        .for_each_with(sender, |s, record| {
-            let fasta_record = record.expect("[-]\tError during fasta record parsing.");
-
-            // for the stats at the end.
-            // initiate counters for the windows
-            let mut start = 0;
-            // https://github.com/tolkit/fasta_windows/issues/9
-            // if the record length < window size, set end to the
-            let mut end = match fasta_record.seq().len() < window_size {
-                true => fasta_record.seq().len(),
-                false => window_size,
-            };
+    // sorting needs every record before the first can be written,
+    // so only then are the results held back.
+    let mut sorted: Vec<RecordWindows> = Vec::new();
+    process_in_order(
+        input,
+        InFlight::default(),
+        |record| record.seq.len(),
+        |record| record_windows(config, record),
+        |record| {
+            if let Some(progress) = &mut progress {
+                progress.inc(record.len);
+            }
+            match config.sort {
+                Some(_) => sorted.push(record),
+                None => {
+                    for output in &mut outputs {
+                        output.write_record(&record)?;
+                    }
+                }
+            }
+            Ok(())
+        },
+    )?;
+    if let Some(progress) = progress {
+        progress.finish();
+    }
 
-            // begin sliding windows
-            // consider changing this to chunks_exact?
-            let windows = fasta_record.seq().chunks(window_size);
+    // within a sequence windows always stay in coordinate order.
+    if let Some(order) = config.sort {
+        // by the length of the whole sequence, so its regions stay together
+        sorted.sort_by(|a, b| order.compare((&a.id, a.full_len), (&b.id, b.full_len)));
+        for record in &sorted {
+            for output in &mut outputs {
+                output.write_record(record)?;
+            }
+        }
+    }
+    for output in outputs {
+        output.finish()?;
+    }
 
-            for win in windows {
-                let seq_stats = seq_statsu8::seq_stats(win, masked);
+    Ok(())
+}
 
-                // unpack values
-                let kmer_stats = kmeru8::kmer_diversity(win, kmer_maps.clone());
+/// The statistics of every window of `input`, one at a time, without
+/// writing anything. Sequences are processed in input order as the
+/// windows are taken, each in parallel; `sort` is ignored.
+pub fn windows(config: &Config, input: Input) -> Windows<'_> {
+    Windows {
+        config,
+        input,
+        current: None,
+    }
+}
 
-                let ctw_bpb = if ctw {
-                    kmeru8::ctw_bits_per_base_dna(win, 6)
-                } else {
-                    0.0
-                };
-
-                // get description if present
-                let desc = match fasta_record.desc() {
-                    Some(d) => d.to_string(),
-                    None => "No description.".to_string(),
-                };
-
-                s.send(Entry {
-                    id: fasta_record.id().to_string(),
-                    desc,
-                    start,
-                    end,
-                    nuc_counts: seq_stats.nuc_counts,
-                    gc_proportion: seq_stats.gc_proportion,
-                    gc_skew: seq_stats.gc_skew,
-                    at_skew: seq_stats.at_skew,
-                    shannon_entropy: seq_stats.shannon_entropy,
-                    ctw_bpb,
-                    g_s: seq_stats.g_s,
-                    c_s: seq_stats.c_s,
-                    a_s: seq_stats.a_s,
-                    t_s: seq_stats.t_s,
-                    n_s: seq_stats.n_s,
-                    masked: seq_stats.masked,
-                    cpg_s: ((*kmer_stats.di_freq.get(6).unwrap_or(&0) as f32) / seq_stats.len),
-                    dinucleotides: kmer_stats.dinucleotides,
-                    trinucleotides: kmer_stats.trinucleotides,
-                    tetranucleotides: kmer_stats.tetranucleotides,
-                    divalues: kmer_stats.di_freq,
-                    trivalues: kmer_stats.tri_freq,
-                    tetravalues: kmer_stats.tetra_freq,
-                })
-                .unwrap();
-
-                // re-set the counter if counter > length of current sequence
-
-                if end < fasta_record.seq().len() {
-                    start += window_size;
-                    end += window_size;
-
-                    // now check if end overshoots the actual sequence length
-                    // issue #8
-                    if end > fasta_record.seq().len() {
-                        end = fasta_record.seq().len()
-                    }
-                } else {
-                    start = 0;
-                    end = window_size;
+/// An iterator over the windows of a fasta, made by [`windows`].
+pub struct Windows<'a> {
+    config: &'a Config,
+    input: Input,
+    // the ID and the windows still to come of the sequence being read
+    current: Option<(Arc<str>, std::vec::IntoIter<Entry>)>,
+}
+
+/// The statistics of one window of a sequence.
+pub struct Window {
+    pub id: Arc<str>,
+    pub entry: Entry,
+}
+
+impl Iterator for Windows<'_> {
+    type Item = Result<Window>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((id, entries)) = &mut self.current {
+                if let Some(entry) = entries.next() {
+                    return Some(Ok(Window {
+                        id: id.clone(),
+                        entry,
+                    }));
                 }
             }
-            progress_bar.inc(1);
-        });
-    progress_bar.finish();
-    let mut entries: Vec<Entry> = receiver.iter().collect();
-    // parallel iteration messes up the order of scaffold ID's, so we fix that here.
-    // however, within ID's windows should be ordered.
-    entries.sort_by_key(|x| x.id.clone());
-
-    let mut entry_writer = Output(entries);
-
-    eprintln!("[+]\tWriting output to files");
-
-    entry_writer.write_windows(&mut window_file_0, description, ctw)?;
-    entry_writer.write_kmers(
-        &mut window_file_1,
-        &mut window_file_2,
-        &mut window_file_3,
-        &mut window_file_4,
-        kmer_maps,
-        description,
-    )?;
+            let record = match self.input.next()? {
+                Ok(record) => record_windows(self.config, record),
+                Err(e) => Err(e),
+            };
+            match record {
+                Ok(record) => self.current = Some((record.id.into(), record.entries.into_iter())),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// A writer that can also seek, as a bigWig's has to.
+pub trait WriteSeek: Write + Seek + Send {}
+
+impl<W: Write + Seek + Send> WriteSeek for W {}
+
+/// Somewhere to write one of the outputs of [`fasta_windows`].
+pub enum Sink<'a> {
+    /// Any writer, for everything but bigWig tracks.
+    Stream(Box<dyn Write + Send + 'a>),
+    /// A writer that can seek, for any output.
+    Seekable(Box<dyn WriteSeek + 'a>),
+}
+
+impl<'a> Sink<'a> {
+    pub fn stream(out: impl Write + Send + 'a) -> Self {
+        Sink::Stream(Box::new(out))
+    }
+
+    pub fn seekable(out: impl Write + Seek + Send + 'a) -> Self {
+        Sink::Seekable(Box::new(out))
+    }
+
+    // the writer, if it can seek
+    pub(crate) fn into_seekable(self) -> Result<Box<dyn WriteSeek + 'a>> {
+        match self {
+            Sink::Seekable(out) => Ok(out),
+            Sink::Stream(_) => {
+                anyhow::bail!("bigWig tracks need a sink that can seek, such as a file or a Cursor")
+            }
+        }
+    }
+}
+
+impl Write for Sink<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Sink::Stream(out) => out.write(buf),
+            Sink::Seekable(out) => out.write(buf),
+        }
+    }
 
-    eprintln!(
-        "[+]\tOutput written to directory: ./fw_out/{}",
-        output.display()
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Sink::Stream(out) => out.flush(),
+            Sink::Seekable(out) => out.flush(),
+        }
+    }
+}
+
+// where each kind of output is written, taking the sinks in the order of
+// `Config::output_suffixes`
+fn outputs<'a>(
+    config: &Config,
+    mut sinks: Vec<Sink<'a>>,
+) -> Result<Vec<Box<dyn WriteWindows + 'a>>> {
+    anyhow::ensure!(
+        sinks.len() == config.output_suffixes().len(),
+        "expected {} outputs, not {}",
+        config.output_suffixes().len(),
+        sinks.len()
     );
+    let mut track_sinks = sinks.split_off(config.table_count());
+    let mut gap_sinks = track_sinks.split_off(config.tracks.len() * config.track_formats.len());
+    let mut masked_sinks = gap_sinks.split_off(config.gaps as usize + config.agp as usize);
+    let mut str_sinks = masked_sinks.split_off(config.masked_runs as usize);
+    let mut dust_sinks = str_sinks.split_off(config.str_bed as usize);
+    let summary_sinks = dust_sinks.split_off(config.dust_bed as usize);
+
+    let output: Box<dyn WriteWindows + 'a> = match config.format {
+        Format::Tsv => {
+            // the rho, motif and tandem repeat tables come after the k-mers
+            let tandem = config
+                .tandem_repeats
+                .then(|| sinks.pop().expect("one sink per suffix"));
+            let motifs = (!config.motifs.is_empty()).then(|| {
+                let names = config.motifs.iter().map(|m| m.name.clone()).collect();
+                (names, sinks.pop().expect("one sink per suffix"))
+            });
+            let rho = config
+                .rho
+                .map(|rho| (rho, sinks.pop().expect("one sink per suffix")));
+            let mut sinks = sinks.into_iter();
+            let mut output = Output {
+                windows: sinks.next().expect("one sink per suffix"),
+                mononucs: sinks.next().expect("one sink per suffix"),
+                kmers: config.kmer_maps.iter().copied().zip(sinks).collect(),
+                rho,
+                motifs,
+                tandem,
+                description: config.description,
+                ctw: config.ctw,
+                gaps: config.gaps,
+                masked_runs: config.masked_runs,
+                strs: config.strs,
+                complexity: config.complexity,
+            };
+            output.write_windows_header()?;
+            output.write_kmers_header()?;
+            Box::new(output)
+        }
+        _ => Box::new(ColumnarOutput::new(sinks, config)?),
+    };
+    let mut outputs = vec![output];
+    if !config.tracks.is_empty() {
+        outputs.push(Box::new(Tracks::new(
+            &config.tracks,
+            &config.track_formats,
+            track_sinks,
+            config.window_size,
+            config.step,
+        )?));
+    }
+    if config.gaps || config.agp {
+        let mut sinks = gap_sinks.into_iter();
+        let bed = config
+            .gaps
+            .then(|| sinks.next().expect("one sink per suffix"));
+        let agp = config
+            .agp
+            .then(|| sinks.next().expect("one sink per suffix"));
+        outputs.push(Box::new(Gaps::new(bed, agp)?));
+    }
+    if let Some(bed) = masked_sinks.pop() {
+        outputs.push(Box::new(MaskedRuns::new(bed)));
+    }
+    if let Some(bed) = str_sinks.pop() {
+        outputs.push(Box::new(Strs::new(bed)));
+    }
+    if let Some(bed) = dust_sinks.pop() {
+        outputs.push(Box::new(Dust::new(bed)));
+    }
+    if config.summary {
+        let mut sinks = summary_sinks.into_iter();
+        outputs.push(Box::new(Summary::new(
+            sinks.next().expect("one sink per suffix"),
+            sinks.next().expect("one sink per suffix"),
+            &config.kmer_maps,
+            config.description,
+            config.masked,
+        )));
+    }
+    Ok(outputs)
+}
 
-    Ok(())
+// the windows of one record, with long sequences split into blocks of
+// windows that run in parallel, so a few huge chromosomes still keep
+// every thread busy. each block slides its own counts along from its
+// first window.
+fn record_windows(config: &Config, fasta_record: FastaRecord) -> Result<RecordWindows> {
+    let seq = &fasta_record.seq;
+    check_sequence(&fasta_record.id, seq, fasta_record.offset)?;
+    let windows = SlidingWindows::new(seq.len(), config.window_size, config.step)?;
+
+    let block = (BLOCK_BASES / config.step).max(1);
+    let mut entries: Vec<Entry> = (0..windows.count().div_ceil(block))
+        .into_par_iter()
+        .flat_map_iter(|b| {
+            let indices = b * block..((b + 1) * block).min(windows.count());
+            let mut entries = window_entries(seq, indices.map(|i| windows.get(i)), config);
+            // window coordinates are on the whole sequence, even for a region
+            for entry in &mut entries {
+                entry.start += fasta_record.offset;
+                entry.end += fasta_record.offset;
+            }
+            entries
+        })
+        .collect();
+
+    if !config.motifs.is_empty() {
+        let starts: Vec<Vec<usize>> = config
+            .motifs
+            .par_iter()
+            .map(|motif| {
+                let mut starts = motif.matches(seq, config.overlapping_motifs);
+                starts.iter_mut().for_each(|i| *i += fasta_record.offset);
+                starts
+            })
+            .collect();
+        entries.par_iter_mut().for_each(|entry| {
+            entry.motifs =
+                motifs::count_in_window(&config.motifs, &starts, (entry.start, entry.end));
+        });
+    }
+
+    let gaps = (config.gaps || config.agp).then(|| {
+        let mut gaps = gaps::find_gaps(seq, config.min_gap);
+        for gap in &mut gaps {
+            gap.0 += fasta_record.offset;
+            gap.1 += fasta_record.offset;
+        }
+        gaps
+    });
+    if let Some(gaps) = gaps.as_deref().filter(|_| config.gaps) {
+        gaps::count_window_gaps(&mut entries, gaps);
+    }
+    let masked_runs = config.masked_runs.then(|| {
+        let mut runs = masked::find_masked_runs(seq);
+        for run in &mut runs {
+            run.0 += fasta_record.offset;
+            run.1 += fasta_record.offset;
+        }
+        masked::count_window_masked_runs(&mut entries, &runs);
+        runs
+    });
+    let strs = (config.strs || config.str_bed).then(|| {
+        let mut loci = microsatellites::find_strs(seq, &config.str_min_copies);
+        for locus in &mut loci {
+            locus.start += fasta_record.offset;
+            locus.end += fasta_record.offset;
+        }
+        if config.strs {
+            microsatellites::count_window_strs(&mut entries, &loci);
+        }
+        loci
+    });
+    let dust = config.dust_bed.then(|| {
+        let mut intervals =
+            complexity::dust_intervals(seq, config.dust_window, config.dust_threshold);
+        for interval in &mut intervals {
+            interval.0 += fasta_record.offset;
+            interval.1 += fasta_record.offset;
+        }
+        intervals
+    });
+
+    Ok(RecordWindows {
+        id: fasta_record.id,
+        // get description if present
+        desc: fasta_record
+            .desc
+            .unwrap_or_else(|| "No description.".to_string()),
+        len: seq.len(),
+        full_len: fasta_record.full_len,
+        entries,
+        totals: config
+            .summary
+            .then(|| SequenceTotals::new(seq, &config.kmer_maps, config.min_gap)),
+        gaps,
+        masked_runs,
+        strs,
+        dust,
+    })
 }
 
-// the output struct
-pub struct Entry {
+// roughly how much sequence each parallel block of windows covers
+const BLOCK_BASES: usize = 1 << 20;
+
+// the code of the CG dinucleotide
+pub(crate) const CG: usize = 0b01_10;
+
+// the statistics of consecutive windows of a sequence.
+pub(crate) fn window_entries(
+    seq: &[u8],
+    windows: impl Iterator<Item = (usize, usize)>,
+    config: &Config,
+) -> Vec<Entry> {
+    let kmer_maps = &config.kmer_maps;
+    let mut entries = Vec::new();
+
+    // counts are carried from one window to the next, so with
+    // overlapping windows only the bases entering and leaving the
+    // window are touched.
+    let mut byte_counts = seq_statsu8::ByteCounts::new();
+    let mut kmer_counts = kmeru8::SlidingKmers::new(kmer_maps);
+    let stranded_dinucs = KmerMap {
+        len: 2,
+        canonical: false,
+    };
+    let mut dinuc_counts = (!kmer_maps.contains(&stranded_dinucs))
+        .then(|| kmeru8::SlidingKmers::new(&[stranded_dinucs]));
+    let mut previous = (0, 0);
+
+    for (start, end) in windows {
+        let win = &seq[start..end];
+
+        let overlaps = slide_range(previous, (start, end), |pos, delta| match delta {
+            1 => byte_counts.add(seq[pos]),
+            _ => byte_counts.remove(seq[pos]),
+        });
+        if !overlaps {
+            byte_counts.clear();
+            win.iter().for_each(|&b| byte_counts.add(b));
+        }
+        kmer_counts.slide(seq, (start, end));
+        previous = (start, end);
+
+        let seq_stats = seq_statsu8::seq_stats_from_counts(&byte_counts, config.masked);
+
+        // the CpGs and rho need the stranded dinucleotide counts, even if
+        // they aren't output, and the bases counted the same way
+        if let Some(dinuc_counts) = &mut dinuc_counts {
+            dinuc_counts.slide(seq, (start, end));
+        }
+        let dinucs = dinuc_counts.as_ref().unwrap_or(&kmer_counts);
+        let dinuc = |code| dinucs.count(2, code).expect("dinucleotides are counted");
+        let cpg = dinuc(CG);
+        let acgt = byte_counts.acgt();
+        let cpg_oe = seq_statsu8::cpg_observed_expected(&acgt, cpg as u64);
+        let rho = match config.rho {
+            Some(rho) => seq_statsu8::karlin_rho(&acgt, &std::array::from_fn(dinuc), rho.canonical),
+            None => Vec::new(),
+        };
+
+        let (longest_homopolymer, homopolymers) = match config.strs {
+            true => microsatellites::window_homopolymers(win, config.min_homopolymer),
+            false => (0, 0),
+        };
+
+        let (dust, wf_complexity) = match config.complexity {
+            true => (
+                complexity::dust_score(win),
+                complexity::wootton_federhen(&complexity::acgt_counts(&byte_counts, config.masked)),
+            ),
+            false => (0.0, 0.0),
+        };
+
+        // the context tree can't forget bases, so is rebuilt for each window
+        let ctw_bpb = if config.ctw {
+            kmeru8::ctw_bits_per_base_dna(win, config.ctw_depth)
+        } else {
+            0.0
+        };
+
+        entries.push(Entry {
+            start,
+            end,
+            nuc_counts: seq_stats.nuc_counts,
+            gc_proportion: seq_stats.gc_proportion,
+            gc_skew: seq_stats.gc_skew,
+            at_skew: seq_stats.at_skew,
+            shannon_entropy: seq_stats.shannon_entropy,
+            ctw_bpb,
+            g_s: seq_stats.g_s,
+            c_s: seq_stats.c_s,
+            a_s: seq_stats.a_s,
+            t_s: seq_stats.t_s,
+            n_s: seq_stats.n_s,
+            masked: seq_stats.masked,
+            cpg_s: (cpg as f32) / seq_stats.len,
+            cpg_oe,
+            kmers: kmer_counts.spectra(),
+            rho,
+            motifs: Vec::new(),
+            tandem: match config.tandem_repeats {
+                true => tandem::tandem_repeat(win),
+                false => None,
+            },
+            gaps: 0,
+            gap_bases: 0,
+            masked_runs: 0,
+            longest_masked_run: 0,
+            mean_masked_run: 0.0,
+            longest_homopolymer,
+            homopolymers,
+            str_coverage: 0.0,
+            dust,
+            wf_complexity,
+        });
+    }
+    entries
+}
+
+// the windows of one fasta record
+pub struct RecordWindows {
     // the id from the fasta file
     pub id: String,
     // the description from the fasta file
     pub desc: String,
+    // the length of the sequence (or region) analysed
+    pub len: usize,
+    // the length of the whole sequence in the fasta
+    pub full_len: usize,
+    // the statistics for each window, in order
+    pub entries: Vec<Entry>,
+    // the counts over the whole sequence, for a summary
+    pub totals: Option<SequenceTotals>,
+    // the runs of Ns, if asked for
+    pub gaps: Option<Vec<(usize, usize)>>,
+    // the runs of soft-masked bases, if asked for
+    pub masked_runs: Option<Vec<(usize, usize)>>,
+    // the short tandem repeats, if asked for
+    pub strs: Option<Vec<StrLocus>>,
+    // the intervals masked by DUST, if asked for
+    pub dust: Option<Vec<(usize, usize)>>,
+}
+
+// the output struct
+#[derive(Debug, Clone)]
+pub struct Entry {
     // the start of the window
     pub start: usize,
     // the end of the window
     pub end: usize,
     // the nucleotide counts
-    pub nuc_counts: Vec<i32>,
+    pub nuc_counts: Vec<i64>,
     // the gc proportion
     pub gc_proportion: f32,
     // gc skew
@@ -209,41 +559,146 @@ pub struct Entry {
     pub masked: f32,
     // number of cpg sites
     pub cpg_s: f32,
-    // dinucleotide shannon entropy
-    pub dinucleotides: f64,
-    // trinucleotide shannon entropy
-    pub trinucleotides: f64,
-    // tetranucleotide shannon entropy
-    pub tetranucleotides: f64,
-    // the frequency distributions of
-    // each of the 3 kmer classes
-    pub divalues: Vec<i32>,
-    pub trivalues: Vec<i32>,
-    pub tetravalues: Vec<i32>,
+    // cpg observed/expected
+    pub cpg_oe: f32,
+    // the shannon diversity and counts of each k-mer size
+    pub kmers: Vec<KmerSpectrum>,
+    // karlin's rho of each dinucleotide, if asked for
+    pub rho: Vec<f32>,
+    // the number of matches of each motif, if any
+    pub motifs: Vec<u32>,
+    // the dominant tandem repeat, if asked for and there is one
+    pub tandem: Option<TandemRepeat>,
+    // the number of gaps in the window, and of bases in them, if asked for
+    pub gaps: usize,
+    pub gap_bases: usize,
+    // the number of soft-masked runs in the window, and the longest and mean
+    // length of them within it, if asked for
+    pub masked_runs: usize,
+    pub longest_masked_run: usize,
+    pub mean_masked_run: f32,
+    // the longest homopolymer in the window, the number at least the
+    // minimum length, and the proportion of the window in short tandem
+    // repeats, if asked for
+    pub longest_homopolymer: usize,
+    pub homopolymers: usize,
+    pub str_coverage: f32,
+    // the DUST score and Wootton-Federhen complexity, if asked for
+    pub dust: f32,
+    pub wf_complexity: f32,
 }
 
-pub struct Output(Vec<Entry>);
-
-impl Output {
-    // write the windows file, optionally including a description
-    pub fn write_windows(
-        &mut self,
-        file: &mut BufWriter<File>,
-        description: bool,
-        ctw: bool,
-    ) -> Result<()> {
-        let header= match (description, ctw) {
-            (true, true) => "ID\tdescription\tstart\tend\tGC_prop\tGC_skew\tAT_skew\tShannon_entropy\tctw\tProp_Gs\tProp_Cs\tProp_As\tProp_Ts\tProp_Ns\tProp_masked\tCpG_prop\tDinucleotide_Shannon\tTrinucleotide_Shannon\tTetranucleotide_Shannon".to_string(),
-            (false, true) => "ID\tstart\tend\tGC_prop\tGC_skew\tAT_skew\tShannon_entropy\tctw\tProp_Gs\tProp_Cs\tProp_As\tProp_Ts\tProp_Ns\tProp_masked\tCpG_prop\tDinucleotide_Shannon\tTrinucleotide_Shannon\tTetranucleotide_Shannon".to_string(),
-            (true, false) => "ID\tdescription\tstart\tend\tGC_prop\tGC_skew\tAT_skew\tShannon_entropy\tProp_Gs\tProp_Cs\tProp_As\tProp_Ts\tProp_Ns\tProp_masked\tCpG_prop\tDinucleotide_Shannon\tTrinucleotide_Shannon\tTetranucleotide_Shannon".to_string(),
-            (false, false) => "ID\tstart\tend\tGC_prop\tGC_skew\tAT_skew\tShannon_entropy\tProp_Gs\tProp_Cs\tProp_As\tProp_Ts\tProp_Ns\tProp_masked\tCpG_prop\tDinucleotide_Shannon\tTrinucleotide_Shannon\tTetranucleotide_Shannon".to_string(),
-        };
+/// Where the windows of each record are written, in one of the output formats.
+pub trait WriteWindows {
+    fn write_record(&mut self, record: &RecordWindows) -> Result<()>;
+
+    // write anything left over, and close the files
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+// the TSV output files, which are written a record at a time
+pub struct Output<W: Write> {
+    pub windows: W,
+    pub mononucs: W,
+    // the counts of each k-mer size, in the order of `Entry::kmers`
+    pub kmers: Vec<(KmerMap, W)>,
+    // karlin's rho of each of these dinucleotides
+    pub rho: Option<(KmerMap, W)>,
+    // the counts of each of these motifs
+    pub motifs: Option<(Vec<String>, W)>,
+    // the tandem repeat of each window
+    pub tandem: Option<W>,
+    // add a column for the fasta header descriptions
+    pub description: bool,
+    // add a column for the context-tree weighting
+    pub ctw: bool,
+    // add columns for the gaps
+    pub gaps: bool,
+    // add columns for the soft-masked runs
+    pub masked_runs: bool,
+    // add columns for the homopolymers and short tandem repeats
+    pub strs: bool,
+    // add columns for the DUST and Wootton-Federhen complexity
+    pub complexity: bool,
+}
+
+impl<W: Write> WriteWindows for Output<W> {
+    fn write_record(&mut self, record: &RecordWindows) -> Result<()> {
+        self.write_windows(record)?;
+        self.write_kmers(record)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: Write> Output<W> {
+    pub fn flush(&mut self) -> Result<()> {
+        self.windows.flush()?;
+        self.mononucs.flush()?;
+        for (_, file) in self.kmers.iter_mut().chain(&mut self.rho) {
+            file.flush()?;
+        }
+        if let Some((_, file)) = &mut self.motifs {
+            file.flush()?;
+        }
+        if let Some(file) = &mut self.tandem {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    // the first columns of every file
+    fn id_header(&self) -> &'static str {
+        match self.description {
+            true => "ID\tdescription\tstart\tend",
+            false => "ID\tstart\tend",
+        }
+    }
+
+    // the header of the windows file, optionally including a description
+    pub fn write_windows_header(&mut self) -> Result<()> {
+        let mut header = vec![
+            self.id_header().to_string(),
+            "GC_prop\tGC_skew\tAT_skew\tShannon_entropy".to_string(),
+        ];
+        if self.ctw {
+            header.push("ctw".to_string());
+        }
+        header.push(
+            "Prop_Gs\tProp_Cs\tProp_As\tProp_Ts\tProp_Ns\tProp_masked\tCpG_prop\tCpG_oe"
+                .to_string(),
+        );
+        if self.gaps {
+            header.push("gaps\tgap_bases".to_string());
+        }
+        if self.masked_runs {
+            header.push("masked_runs\tlongest_masked_run\tmean_masked_run".to_string());
+        }
+        if self.strs {
+            header.push("longest_homopolymer\thomopolymers\tSTR_coverage".to_string());
+        }
+        if self.complexity {
+            header.push("DUST\tWF_complexity".to_string());
+        }
+        for (kmer_map, _) in &self.kmers {
+            header.push(format!("{}_Shannon", kmer_map.name()));
+        }
 
-        writeln!(file, "{header}")?;
+        writeln!(self.windows, "{}", header.join("\t"))?;
+        Ok(())
+    }
+
+    // write the windows of a record
+    pub fn write_windows(&mut self, record: &RecordWindows) -> Result<()> {
+        let id = &record.id;
+        let desc = match self.description {
+            true => format!("{}\t", record.desc),
+            false => String::new(),
+        };
 
         for Entry {
-            id,
-            desc,
             start,
             end,
             nuc_counts: _,
@@ -259,128 +714,190 @@ impl Output {
             n_s,
             masked,
             cpg_s,
-            dinucleotides,
-            trinucleotides,
-            tetranucleotides,
-            divalues: _,
-            trivalues: _,
-            tetravalues: _,
-        } in &self.0
+            cpg_oe,
+            kmers,
+            rho: _,
+            motifs: _,
+            tandem: _,
+            gaps,
+            gap_bases,
+            masked_runs,
+            longest_masked_run,
+            mean_masked_run,
+            longest_homopolymer,
+            homopolymers,
+            str_coverage,
+            dust,
+            wf_complexity,
+        } in &record.entries
         {
-            let desc = match description {
-                true => format!("{desc}\t"),
+            let ctw_format = match self.ctw {
+                true => format!("{ctw_bpb:.3}\t"),
                 false => String::new(),
             };
-
-            let ctw_format = match ctw {
-                true => format!("{ctw_bpb:.3}\t"),
+            let gaps_format = match self.gaps {
+                true => format!("\t{gaps}\t{gap_bases}"),
+                false => String::new(),
+            };
+            let masked_runs_format = match self.masked_runs {
+                true => format!("\t{masked_runs}\t{longest_masked_run}\t{mean_masked_run:.3}"),
+                false => String::new(),
+            };
+            let strs_format = match self.strs {
+                true => format!("\t{longest_homopolymer}\t{homopolymers}\t{str_coverage:.3}"),
                 false => String::new(),
             };
+            let complexity_format = match self.complexity {
+                true => format!("\t{dust:.3}\t{wf_complexity:.3}"),
+                false => String::new(),
+            };
+            let kmer_shannon = kmers
+                .iter()
+                .map(|spectrum| format!("\t{:.3}", spectrum.shannon))
+                .collect::<String>();
 
             writeln!(
-                file,
-                "{id}\t{desc}{start}\t{end}\t{gc_proportion:.3}\t{gc_skew:.3}\t{at_skew:.3}\t{shannon_entropy:.3}\t{ctw_format}{g_s:.3}\t{c_s:.3}\t{a_s:.3}\t{t_s:.3}\t{n_s:.3}\t{masked:.3}\t{cpg_s:.3}\t{dinucleotides:.3}\t{trinucleotides:.3}\t{tetranucleotides:.3}",
+                self.windows,
+                "{id}\t{desc}{start}\t{end}\t{gc_proportion:.3}\t{gc_skew:.3}\t{at_skew:.3}\t{shannon_entropy:.3}\t{ctw_format}{g_s:.3}\t{c_s:.3}\t{a_s:.3}\t{t_s:.3}\t{n_s:.3}\t{masked:.3}\t{cpg_s:.3}\t{cpg_oe:.3}{gaps_format}{masked_runs_format}{strs_format}{complexity_format}{kmer_shannon}",
             )?;
         }
-        file.flush()?;
 
         Ok(())
     }
 
-    pub fn write_kmers(
-        &mut self,
-        file1: &mut BufWriter<File>,
-        file2: &mut BufWriter<File>,
-        file3: &mut BufWriter<File>,
-        file4: &mut BufWriter<File>,
-        kmer_maps: Vec<KmerMap>,
-        description: bool,
-    ) -> Result<()> {
-        // these are the arrays, tab separated bed-like format.
-        // unpack kmer_maps
-        match kmer_maps.as_slice() {
-            [two, three, four] => {
-                // headers for all
-                let header = match description {
-                    true => "ID\tdescription\tstart\tend\t".to_string(),
-                    false => "ID\tstart\tend\t".to_string(),
-                };
-
-                // headers for mononucs
-                writeln!(file1, "{header}A\tC\tG\tT\tN")?;
-                // headers for dinucs
-                let mut dinuc_headers = Vec::new();
-                for key in two.map.keys().sorted() {
-                    dinuc_headers.push(key)
-                }
-                let dinucs = WriteKmerValues(dinuc_headers);
-                writeln!(file2, "{header}{dinucs}")?;
-                // headers for trinucs
-                let mut trinuc_headers = Vec::new();
-                for key in three.map.keys().sorted() {
-                    trinuc_headers.push(key)
-                }
-                let trinucs = WriteKmerValues(trinuc_headers);
-                writeln!(file3, "{header}{trinucs}")?;
-                // headers for tetranucs
-                let mut tetranuc_headers = Vec::new();
-                for key in four.map.keys().sorted() {
-                    tetranuc_headers.push(key)
-                }
-                let tetranucs = WriteKmerValues(tetranuc_headers);
-                writeln!(file4, "{header}{tetranucs}")?;
-
-                for Entry {
-                    id,
-                    desc,
-                    start,
-                    end,
-                    nuc_counts,
-                    gc_proportion: _,
-                    gc_skew: _,
-                    at_skew: _,
-                    shannon_entropy: _,
-                    ctw_bpb: _,
-                    g_s: _,
-                    c_s: _,
-                    a_s: _,
-                    t_s: _,
-                    n_s: _,
-                    masked: _,
-                    cpg_s: _,
-                    dinucleotides: _,
-                    trinucleotides: _,
-                    tetranucleotides: _,
-                    divalues,
-                    trivalues,
-                    tetravalues,
-                } in &self.0
-                {
-                    let desc = match description {
-                        true => format!("{desc}\t"),
-                        false => String::new(),
-                    };
-
-                    let nuc_counts = WriteArray(nuc_counts.clone());
-                    writeln!(file1, "{id}\t{desc}{start}\t{end}\t{nuc_counts}")?;
-
-                    let divalues_vec = WriteArray(divalues.clone());
-                    writeln!(file2, "{id}\t{desc}{start}\t{end}\t{divalues_vec}",)?;
-
-                    let trivalues_vec = WriteArray(trivalues.clone());
-                    writeln!(file3, "{id}\t{desc}{start}\t{end}\t{trivalues_vec}",)?;
-
-                    let tetravalues_vec = WriteArray(tetravalues.clone());
-                    writeln!(file4, "{id}\t{desc}{start}\t{end}\t{tetravalues_vec}",)?;
+    // the headers of the kmer files. Dense k-mers have a column each (only
+    // the canonical ones with --canonical), in lexicographic order, and the
+    // rest a row for each k-mer present.
+    pub fn write_kmers_header(&mut self) -> Result<()> {
+        let header = self.id_header();
+        writeln!(self.mononucs, "{header}\tA\tC\tG\tT\tN")?;
+        for (kmer_map, file) in &mut self.kmers {
+            match kmer_map.is_dense() {
+                true => writeln!(file, "{header}\t{}", kmer_map.kmers().join("\t"))?,
+                false => writeln!(file, "{header}\tkmer\tcount")?,
+            }
+        }
+        if let Some((dinucs, file)) = &mut self.rho {
+            writeln!(file, "{header}\t{}", dinucs.kmers().join("\t"))?;
+        }
+        if let Some((names, file)) = &mut self.motifs {
+            writeln!(file, "{header}\t{}", names.join("\t"))?;
+        }
+        if let Some(file) = &mut self.tandem {
+            writeln!(file, "{header}\tperiod\tcoverage\tmonomer")?;
+        }
+        Ok(())
+    }
+
+    // write the kmer frequency arrays for the windows of a record
+    pub fn write_kmers(&mut self, record: &RecordWindows) -> Result<()> {
+        let id = &record.id;
+        let desc = match self.description {
+            true => format!("{}\t", record.desc),
+            false => String::new(),
+        };
+
+        for entry in &record.entries {
+            let (start, end) = (entry.start, entry.end);
+            let nuc_counts = WriteArray(&entry.nuc_counts);
+            writeln!(self.mononucs, "{id}\t{desc}{start}\t{end}\t{nuc_counts}")?;
+
+            for ((kmer_map, file), spectrum) in self.kmers.iter_mut().zip(&entry.kmers) {
+                if kmer_map.is_dense() {
+                    let counts = WriteArray(&spectrum.dense());
+                    writeln!(file, "{id}\t{desc}{start}\t{end}\t{counts}")?;
+                } else {
+                    for &(code, count) in &spectrum.counts {
+                        let kmer = kmer_maps::decode(code, kmer_map.len);
+                        writeln!(file, "{id}\t{desc}{start}\t{end}\t{kmer}\t{count}")?;
+                    }
                 }
+            }
+
+            if let Some((_, file)) = &mut self.rho {
+                let rho: Vec<String> = entry.rho.iter().map(|r| format!("{r:.3}")).collect();
+                writeln!(file, "{id}\t{desc}{start}\t{end}\t{}", rho.join("\t"))?;
+            }
 
-                file1.flush()?;
-                file2.flush()?;
-                file3.flush()?;
-                file4.flush()?;
+            if let Some((_, file)) = &mut self.motifs {
+                let counts = WriteArray(&entry.motifs);
+                writeln!(file, "{id}\t{desc}{start}\t{end}\t{counts}")?;
+            }
+
+            if let Some(file) = &mut self.tandem {
+                match &entry.tandem {
+                    Some(TandemRepeat {
+                        period,
+                        coverage,
+                        monomer,
+                    }) => writeln!(
+                        file,
+                        "{id}\t{desc}{start}\t{end}\t{period}\t{coverage:.3}\t{monomer}"
+                    )?,
+                    None => writeln!(file, "{id}\t{desc}{start}\t{end}\t0\t0.000\tNA")?,
+                }
             }
-            [..] => {} // Make the patterns exhaustive
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_match_one_pass() {
+        let seq: Vec<u8> = (0..3000u32)
+            .map(|i| b"ACGTNacgt"[(i * i % 17) as usize % 9])
+            .collect();
+        let config = Config::builder().window_size(100).step(30).build().unwrap();
+        let windows = SlidingWindows::new(seq.len(), 100, 30).unwrap();
+
+        let whole = window_entries(&seq, windows.iter(), &config);
+        let blocks: Vec<Entry> = [0..7, 7..8, 8..windows.count()]
+            .into_iter()
+            .flat_map(|block| {
+                let coords = block.map(|i| windows.get(i));
+                window_entries(&seq, coords, &config)
+            })
+            .collect();
+
+        assert_eq!(whole.len(), blocks.len());
+        for (a, b) in whole.iter().zip(&blocks) {
+            assert_eq!((a.start, a.end), (b.start, b.end));
+            assert_eq!(a.nuc_counts, b.nuc_counts);
+            assert_eq!(a.kmers, b.kmers);
+        }
+    }
+
+    #[test]
+    fn test_in_memory_input_and_outputs() {
+        let fasta = b">one first\nACGTACGTAC\nGTACGT\n>two\nCCCCCGGGGG\n".to_vec();
+        let config = Config::builder()
+            .window_size(5)
+            .kmer_sizes(&[2])
+            .tracks(["GC_prop"])
+            .build()
+            .unwrap();
+
+        let mut sinks = vec![Vec::new(); config.output_suffixes().len()];
+        let input = Input::from_reader(std::io::Cursor::new(fasta.clone())).unwrap();
+        fasta_windows(&config, input, sinks.iter_mut().map(Sink::stream).collect()).unwrap();
+        let text = |i: usize| String::from_utf8(sinks[i].clone()).unwrap();
+        let freq = text(0);
+        let lines: Vec<&str> = freq.lines().collect();
+        assert_eq!(lines.len(), 1 + 4 + 2);
+        assert!(lines[1].starts_with("one\t0\t5\t0.400"));
+        assert!(text(2).starts_with("ID\tstart\tend\tAA\tAC"));
+        assert!(text(3).contains("two\t5\t10\t1.000"));
+
+        // the iterator gives the same windows
+        let input = Input::from_reader(std::io::Cursor::new(fasta)).unwrap();
+        let windows: Vec<Window> = windows(&config, input).collect::<Result<_>>().unwrap();
+        assert_eq!(windows.len(), 6);
+        assert_eq!(&*windows[4].id, "two");
+        assert_eq!((windows[4].entry.start, windows[4].entry.end), (0, 5));
+        assert_eq!(windows[5].entry.gc_proportion, 1.0);
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::Write;
+
+use anyhow::{ensure, Result};
+
+use crate::fw::{Entry, RecordWindows, WriteWindows};
+use crate::runs;
+
+/// The runs of Ns (or ns) in `seq` at least `min_len` long, as half-open
+/// ranges.
+pub fn find_gaps(seq: &[u8], min_len: usize) -> Vec<(usize, usize)> {
+    runs::find_runs(seq, min_len, |b| matches!(b, b'N' | b'n'))
+}
+
+/// Set the number of gaps touching each window, and how many of its bases
+/// are in them. The windows and gaps are both in order along the sequence.
+pub fn count_window_gaps(entries: &mut [Entry], gaps: &[(usize, usize)]) {
+    for entry in entries {
+        let overlaps: Vec<usize> = runs::overlaps(gaps, (entry.start, entry.end)).collect();
+        entry.gaps = overlaps.len();
+        entry.gap_bases = overlaps.iter().sum();
+    }
+}
+
+/// The gaps of each sequence as a BED of `ID start end length`, and an AGP
+/// describing each sequence as contigs separated by those gaps.
+pub struct Gaps<W: Write> {
+    bed: Option<W>,
+    agp: Option<W>,
+}
+
+impl<W: Write> Gaps<W> {
+    pub fn new(bed: Option<W>, agp: Option<W>) -> Result<Self> {
+        let mut gaps = Self { bed, agp };
+        if let Some(agp) = &mut gaps.agp {
+            writeln!(agp, "##agp-version\t2.1")?;
+        }
+        Ok(gaps)
+    }
+
+    // the contigs between the gaps, and the gaps, in AGP 2.1 format
+    fn write_agp(agp: &mut W, record: &RecordWindows, gaps: &[(usize, usize)]) -> Result<()> {
+        ensure!(
+            record.len == record.full_len,
+            "an AGP can only be written for whole sequences, not regions"
+        );
+        let id = &record.id;
+        let mut part = 0;
+        let mut contig = 0;
+        let mut write_contig = |agp: &mut W, part: &mut usize, start: usize, end: usize| {
+            if start < end {
+                *part += 1;
+                contig += 1;
+                let len = end - start;
+                writeln!(
+                    agp,
+                    "{id}\t{}\t{end}\t{part}\tW\t{id}_{contig}\t1\t{len}\t+",
+                    start + 1
+                )?;
+            }
+            Ok::<(), anyhow::Error>(())
+        };
+
+        let mut contig_start = 0;
+        for &(start, end) in gaps {
+            write_contig(agp, &mut part, contig_start, start)?;
+            part += 1;
+            writeln!(
+                agp,
+                "{id}\t{}\t{end}\t{part}\tN\t{}\tscaffold\tyes\tunspecified",
+                start + 1,
+                end - start
+            )?;
+            contig_start = end;
+        }
+        write_contig(agp, &mut part, contig_start, record.len)
+    }
+}
+
+impl<W: Write> WriteWindows for Gaps<W> {
+    fn write_record(&mut self, record: &RecordWindows) -> Result<()> {
+        let gaps = record.gaps.as_deref().unwrap_or_default();
+        if let Some(bed) = &mut self.bed {
+            for &(start, end) in gaps {
+                writeln!(bed, "{}\t{start}\t{end}\t{}", record.id, end - start)?;
+            }
+        }
+        if let Some(agp) = &mut self.agp {
+            Self::write_agp(agp, record, gaps)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        for mut file in self.bed.into_iter().chain(self.agp) {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_gaps() {
+        let seq = b"NNACGTnNNNACNGTNN";
+        assert_eq!(find_gaps(seq, 1), [(0, 2), (6, 10), (12, 13), (15, 17)]);
+        assert_eq!(find_gaps(seq, 3), [(6, 10)]);
+        assert!(find_gaps(b"ACGT", 1).is_empty());
+    }
+
+    #[test]
+    fn test_agp() {
+        let record = RecordWindows {
+            id: "scaf1".to_string(),
+            desc: String::new(),
+            len: 20,
+            full_len: 20,
+            entries: Vec::new(),
+            totals: None,
+            gaps: Some(vec![(5, 10)]),
+            masked_runs: None,
+            strs: None,
+            dust: None,
+        };
+        let mut gaps = Box::new(Gaps::new(Some(Vec::new()), Some(Vec::new())).unwrap());
+        gaps.write_record(&record).unwrap();
+        let bed = String::from_utf8(gaps.bed.take().unwrap()).unwrap();
+        assert_eq!(bed, "scaf1\t5\t10\t5\n");
+        let agp = String::from_utf8(gaps.agp.take().unwrap()).unwrap();
+        assert_eq!(
+            agp,
+            "##agp-version\t2.1\n\
+             scaf1\t1\t5\t1\tW\tscaf1_1\t1\t5\t+\n\
+             scaf1\t6\t10\t2\tN\t5\tscaffold\tyes\tunspecified\n\
+             scaf1\t11\t20\t3\tW\tscaf1_2\t1\t10\t+\n"
+        );
+    }
+}
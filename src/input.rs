@@ -0,0 +1,135 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context, Result};
+
+use crate::fai::{Fai, FaiWriter};
+use crate::reader::{is_plain_fasta, open_fasta, read_fasta, BytesRead, FastaRecord};
+use crate::region::{self, IndexedRegions, Region, StreamedRegions};
+
+/// The sequences to analyse: every record of the fasta, or if any regions
+/// are given, just those parts of it.
+///
+/// Regions come out in fasta file order, and in the order they were given
+/// within a sequence. They are read straight from the file if it is
+/// uncompressed and has a `.fai` index, and found by reading through it
+/// otherwise. Every region has to name a sequence in the fasta.
+pub struct Input {
+    records: Box<dyn Iterator<Item = Result<FastaRecord>>>,
+    // the number of sequences and bases to come, if there's an index to say
+    pub expected: Option<(usize, u64)>,
+    // the size of the input file, unless it's stdin
+    pub file_size: Option<u64>,
+    // how much of the file has been read so far
+    pub bytes_read: BytesRead,
+}
+
+impl Input {
+    /// Open the input given on the command line, with any regions, and
+    /// write its index if `--write-fai` was given.
+    pub fn from_args(matches: &clap::ArgMatches) -> Result<Self> {
+        let fasta = matches
+            .get_one::<PathBuf>("fasta")
+            .expect("handled by clap");
+        let regions = region::from_args(matches)?;
+        let index = match matches.get_flag("write_fai") {
+            true => Some(FaiWriter::create(fasta, matches.get_flag("force"))?),
+            false => None,
+        };
+        Self::open(fasta, &regions, index)
+    }
+
+    /// Open `fasta`, or stdin if the path is `-`.
+    ///
+    /// An existing index is used to report progress, unless a new one is
+    /// being written with `index` as the whole file is read.
+    pub fn open(fasta: &Path, regions: &[Region], index: Option<FaiWriter>) -> Result<Self> {
+        ensure!(
+            index.is_none() || regions.is_empty(),
+            "an index can only be written when reading the whole fasta"
+        );
+        let stdin = fasta == Path::new("-");
+        let fai_path = Fai::path_for(fasta);
+        let has_fai = !stdin && index.is_none() && fai_path.exists();
+        let fai = match has_fai && is_plain_fasta(fasta)? {
+            true => Some(Fai::read(&fai_path)?),
+            false => None,
+        };
+
+        let file_size = match stdin {
+            true => None,
+            false => Some(
+                std::fs::metadata(fasta)
+                    .with_context(|| format!("could not open {}", fasta.display()))?
+                    .len(),
+            ),
+        };
+        let expected = fai.as_ref().map(|fai| match regions {
+            [] => (
+                fai.entries().len(),
+                fai.entries().iter().map(|e| e.len as u64).sum(),
+            ),
+            regions => (
+                regions.len(),
+                regions
+                    .iter()
+                    .filter_map(|r| r.bounds(fai.get(&r.name)?.1.len).ok())
+                    .map(|(start, end)| (end - start) as u64)
+                    .sum(),
+            ),
+        });
+
+        let (records, bytes_read): (Box<dyn Iterator<Item = _>>, _) = match (fai, regions) {
+            (_, []) => {
+                let mut records = open_fasta(fasta)?;
+                let bytes_read = records.bytes_read();
+                if let Some(index) = index {
+                    records = records.write_index(index);
+                }
+                (Box::new(records), bytes_read)
+            }
+            (Some(fai), regions) => (
+                Box::new(IndexedRegions::new(fasta, fai, regions)?),
+                BytesRead::default(),
+            ),
+            (None, regions) => {
+                if has_fai {
+                    eprintln!(
+                        "[+]\tThe fasta is compressed, so reading through it to find the regions"
+                    );
+                }
+                let records = open_fasta(fasta)?;
+                let bytes_read = records.bytes_read();
+                (Box::new(StreamedRegions::new(records, regions)), bytes_read)
+            }
+        };
+
+        Ok(Self {
+            records,
+            expected,
+            file_size,
+            bytes_read,
+        })
+    }
+
+    /// Every record of a fasta read from anywhere, such as a buffer or a
+    /// socket, compressed or not.
+    pub fn from_reader(source: impl Read + Send + 'static) -> Result<Self> {
+        let records = read_fasta(source, "input")?;
+        let bytes_read = records.bytes_read();
+        Ok(Self {
+            records: Box::new(records),
+            expected: None,
+            file_size: None,
+            bytes_read,
+        })
+    }
+}
+
+impl Iterator for Input {
+    type Item = Result<FastaRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.records.next()
+    }
+}
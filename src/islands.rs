@@ -0,0 +1,275 @@
+use std::io::prelude::*;
+
+use anyhow::{ensure, Result};
+
+use crate::config::Config;
+use crate::error::check_sequence;
+use crate::input::Input;
+use crate::pipeline::{process_in_order, InFlight};
+use crate::progress::Progress;
+use crate::reader::FastaRecord;
+
+/// What makes a CpG island: the criteria of Gardiner-Garden & Frommer
+/// (1987) by default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IslandCriteria {
+    // the window scanned along the sequence, and so the shortest island
+    pub min_length: usize,
+    // the window's (C + G) / length must be above this
+    pub min_gc: f32,
+    // and its CpG observed/expected ratio
+    pub min_oe: f32,
+    // join islands no more than this many bases apart
+    pub merge_gap: usize,
+}
+
+impl Default for IslandCriteria {
+    fn default() -> Self {
+        Self {
+            min_length: 200,
+            min_gc: 0.5,
+            min_oe: 0.6,
+            merge_gap: 0,
+        }
+    }
+}
+
+impl IslandCriteria {
+    pub fn check(&self) -> Result<()> {
+        ensure!(
+            self.min_length >= 2,
+            "CpG islands must be at least 2 bases long"
+        );
+        ensure!(
+            (0.0..=1.0).contains(&self.min_gc),
+            "the CpG island GC threshold must be between 0 and 1"
+        );
+        ensure!(
+            self.min_oe >= 0.0,
+            "the CpG island o/e threshold can't be negative"
+        );
+        Ok(())
+    }
+}
+
+/// A CpG island, with its coordinates on the sequence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Island {
+    pub start: usize,
+    pub end: usize,
+    pub gc_proportion: f32,
+    pub cpg_oe: f32,
+    pub cpgs: usize,
+}
+
+// Bins each byte into A=0 C=1 G=2 T=3 or other=4, lowercase folded to
+// uppercase, or left as other if only uppercase bases count.
+const BASE_LUT: [u8; 256] = build_base_lut(false);
+const MASKED_BASE_LUT: [u8; 256] = build_base_lut(true);
+const fn build_base_lut(masked: bool) -> [u8; 256] {
+    let mut lut = [4u8; 256];
+    let bases = *b"ACGT";
+    let mut i = 0;
+    while i < 4 {
+        lut[bases[i] as usize] = i as u8;
+        if !masked {
+            lut[bases[i].to_ascii_lowercase() as usize] = i as u8;
+        }
+        i += 1;
+    }
+    lut
+}
+
+// the counts of a stretch of sequence that the criteria need
+#[derive(Debug, Default, Clone, Copy)]
+struct Counts {
+    bases: [usize; 5],
+    cpgs: usize,
+}
+
+impl Counts {
+    fn of(classes: &[u8]) -> Self {
+        let mut counts = Counts::default();
+        for &class in classes {
+            counts.bases[class as usize] += 1;
+        }
+        counts.cpgs = classes.windows(2).filter(|pair| pair == &[1, 2]).count();
+        counts
+    }
+
+    // (C + G) over the whole stretch, so runs of Ns aren't islands
+    fn gc_proportion(&self, len: usize) -> f32 {
+        (self.bases[1] + self.bases[2]) as f32 / len as f32
+    }
+
+    // CpG * (A + C + G + T) / (C * G)
+    fn cpg_oe(&self) -> f32 {
+        let bases = self.bases.map(|count| count as i64);
+        crate::seq_statsu8::cpg_observed_expected(&bases, self.cpgs as u64)
+    }
+}
+
+/// Find the CpG islands in `seq`.
+///
+/// A window of `min_length` slides a base at a time, and every window
+/// meeting both thresholds is kept. Overlapping windows, and those within
+/// `merge_gap` of each other, are joined into one island, whose GC and o/e
+/// are then given over the whole island.
+pub fn find_islands(seq: &[u8], criteria: &IslandCriteria, masked: bool) -> Vec<Island> {
+    let lut = match masked {
+        true => &MASKED_BASE_LUT,
+        false => &BASE_LUT,
+    };
+    let classes: Vec<u8> = seq.iter().map(|&b| lut[b as usize]).collect();
+    let window = criteria.min_length;
+    if classes.len() < window {
+        return Vec::new();
+    }
+    let is_cpg = |i: usize| classes[i] == 1 && classes[i + 1] == 2;
+    let passes = |counts: &Counts| {
+        counts.gc_proportion(window) > criteria.min_gc && counts.cpg_oe() > criteria.min_oe
+    };
+
+    // the merged runs of passing windows
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    let mut keep = |start: usize| match runs.last_mut() {
+        Some(last) if start <= last.1 + criteria.merge_gap => last.1 = start + window,
+        _ => runs.push((start, start + window)),
+    };
+
+    let mut counts = Counts::of(&classes[..window]);
+    if passes(&counts) {
+        keep(0);
+    }
+    for start in 1..=classes.len() - window {
+        // slide along a base: drop the first, add the next
+        let end = start + window;
+        counts.bases[classes[start - 1] as usize] -= 1;
+        counts.bases[classes[end - 1] as usize] += 1;
+        counts.cpgs -= is_cpg(start - 1) as usize;
+        counts.cpgs += is_cpg(end - 2) as usize;
+        if passes(&counts) {
+            keep(start);
+        }
+    }
+
+    runs.into_iter()
+        .map(|(start, end)| {
+            let counts = Counts::of(&classes[start..end]);
+            Island {
+                start,
+                end,
+                gc_proportion: counts.gc_proportion(end - start),
+                cpg_oe: counts.cpg_oe(),
+                cpgs: counts.cpgs,
+            }
+        })
+        .collect()
+}
+
+/// Call CpG islands in each sequence and write them to `bed`, with their
+/// GC proportion, CpG o/e and number of CpGs after the coordinates.
+///
+/// Sequences are scanned in parallel, and written in FASTA order unless
+/// `config` gives a sort order. Only the masking, sort order and island
+/// criteria of `config` are used.
+pub fn cpg_island_windows<W: Write>(config: &Config, input: Input, mut bed: W) -> Result<()> {
+    let mut progress = match config.progress {
+        true => Some(Progress::new(&input)?),
+        false => None,
+    };
+    if config.progress {
+        eprintln!("[+]\tReading fasta (CpG island mode)");
+    }
+    let process = |record: FastaRecord| -> Result<(String, usize, Vec<Island>)> {
+        check_sequence(&record.id, &record.seq, record.offset)?;
+        let mut islands = find_islands(&record.seq, &config.islands, config.masked);
+        for island in &mut islands {
+            island.start += record.offset;
+            island.end += record.offset;
+        }
+        Ok((record.id, record.seq.len(), islands))
+    };
+
+    let mut write = |(id, _, islands): &(String, usize, Vec<Island>)| -> Result<()> {
+        for island in islands {
+            writeln!(
+                bed,
+                "{id}\t{}\t{}\t{:.3}\t{:.3}\t{}",
+                island.start, island.end, island.gc_proportion, island.cpg_oe, island.cpgs
+            )?;
+        }
+        Ok(())
+    };
+
+    // sorting needs every record before the first can be written.
+    let mut sorted = Vec::new();
+    process_in_order(
+        input,
+        InFlight::default(),
+        |record| record.seq.len(),
+        process,
+        |result| {
+            if let Some(progress) = &mut progress {
+                progress.inc(result.1);
+            }
+            match config.sort {
+                Some(_) => sorted.push(result),
+                None => write(&result)?,
+            }
+            Ok(())
+        },
+    )?;
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+
+    if let Some(order) = config.sort {
+        sorted.sort_by(|(a, a_len, _), (b, b_len, _)| order.compare((a, *a_len), (b, *b_len)));
+        for result in &sorted {
+            write(result)?;
+        }
+    }
+    bed.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_islands() {
+        // AT-rich flanks around 300bp of CG repeats
+        let mut seq = b"AT".repeat(200);
+        seq.extend(b"CGGC".repeat(75));
+        seq.extend(b"TA".repeat(200));
+        let criteria = IslandCriteria::default();
+        let islands = find_islands(&seq, &criteria, false);
+        assert_eq!(islands.len(), 1);
+        let island = &islands[0];
+        // windows reaching a little way into the flanks still pass
+        assert!(island.start < 400 && island.start > 200);
+        assert!(island.end > 700 && island.end < 900);
+        assert!(island.gc_proportion > 0.5);
+        assert!(island.cpg_oe > 0.6);
+
+        // nothing in the flanks alone, or in a soft-masked island
+        assert!(find_islands(&seq[..400], &criteria, false).is_empty());
+        assert!(find_islands(&seq.to_ascii_lowercase(), &criteria, true).is_empty());
+
+        // two islands join if they're close enough
+        let mut seq = b"CG".repeat(150);
+        seq.extend(b"A".repeat(300));
+        seq.extend(b"CG".repeat(150));
+        assert_eq!(find_islands(&seq, &criteria, false).len(), 2);
+        let merged = IslandCriteria {
+            merge_gap: 300,
+            ..criteria
+        };
+        let islands = find_islands(&seq, &merged, false);
+        assert_eq!(islands.len(), 1);
+        assert_eq!((islands[0].start, islands[0].end), (0, 900));
+    }
+}
@@ -1,123 +1,294 @@
-use std::collections::HashMap;
 use std::fmt::{Display, Error, Formatter};
 
-use crate::kmeru8::reverse_complement;
+use itertools::Itertools;
 
-#[derive(Debug, Clone)]
+/// The k-mers of one length, counted by their 2-bit code
+/// (A=0, C=1, G=2, T=3, first base in the highest bits).
+///
+/// Codes run in the lexicographic order of the k-mers, so an array of
+/// counts indexed by code is already in output column order.
+///
+/// Canonical k-mers are counted together with their reverse complement,
+/// under whichever of the two codes is smaller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct KmerMap {
     pub len: usize,
-    pub map: HashMap<Vec<u8>, i32>,
+    pub canonical: bool,
 }
 
-pub fn generate_kmer_maps(canonical: bool) -> Vec<KmerMap> {
-    let kmer_maps: Vec<KmerMap> = vec![2, 3, 4]
-        .iter()
-        .map(|i| {
-            let mut kmer_i = gen_all_kmers(*i);
-
-            // if canonical = true, call filter_canonical
-            match canonical {
-                true => {
-                    kmer_i = filter_canonical(kmer_i);
-                }
-                false => (),
-            }
+/// The k-mer sizes counted unless `--kmer-sizes` says otherwise.
+pub const DEFAULT_KMER_SIZES: [usize; 3] = [2, 3, 4];
 
-            let mut kmers_u8: Vec<Vec<u8>> = Vec::new();
-            for i in kmer_i {
-                kmers_u8.push(i.as_bytes().to_vec());
-            }
-            let mut map = HashMap::new();
-            // insert all the possible kmers as keys
-            for k in kmers_u8 {
-                map.insert(k, 0i32);
-            }
+/// The longest k-mers that fit in a 64 bit code.
+pub const MAX_K: usize = 32;
 
-            KmerMap { len: *i, map }
-        })
-        .collect();
-    kmer_maps
-}
+/// Up to this length every possible k-mer gets a column in the output (and
+/// a slot in a flat array while counting). Longer k-mers are too many for
+/// that, so only those present are counted, and written one per line.
+pub const DENSE_MAX_K: usize = 6;
+
+impl KmerMap {
+    /// The number of distinct k-mers, 4^k. Only sensible for dense k.
+    pub fn size(&self) -> usize {
+        1 << (2 * self.len)
+    }
+
+    pub fn is_dense(&self) -> bool {
+        self.len <= DENSE_MAX_K
+    }
+
+    /// The codes of the k-mers that can be counted, in order: every k-mer,
+    /// or only the canonical ones.
+    pub fn codes(&self) -> Vec<usize> {
+        (0..self.size())
+            .filter(|&code| !self.canonical || code <= reverse_complement(code, self.len))
+            .collect()
+    }
+
+    /// Every k-mer counted, in code (and so lexicographic) order.
+    pub fn kmers(&self) -> Vec<String> {
+        self.codes()
+            .into_iter()
+            .map(|code| decode(code, self.len))
+            .collect()
+    }
+
+    /// How to turn the code of a k-mer into the code it's counted under.
+    pub fn code_map(&self) -> CodeMap {
+        match (self.canonical, self.is_dense()) {
+            (false, _) => CodeMap::Stranded,
+            (true, true) => CodeMap::Table(
+                (0..self.size())
+                    .map(|code| code.min(reverse_complement(code, self.len)))
+                    .collect(),
+            ),
+            (true, false) => CodeMap::Computed(self.len),
+        }
+    }
+
+    /// The name of the k-mer class in column headers, e.g. `Dinucleotide`.
+    pub fn name(&self) -> String {
+        match self.len {
+            2 => "Dinucleotide".to_string(),
+            3 => "Trinucleotide".to_string(),
+            4 => "Tetranucleotide".to_string(),
+            k => format!("{k}mer"),
+        }
+    }
 
-// filter Vec<String> of kmers for canonical only.
-// so the hashmap will only contain canonical kmer keys
+    /// The output file of k-mer counts, without its extension, e.g. `dinuc_windows`.
+    pub fn file_stem(&self) -> String {
+        match self.len {
+            2 => "dinuc_windows".to_string(),
+            3 => "trinuc_windows".to_string(),
+            4 => "tetranuc_windows".to_string(),
+            k => format!("{k}mer_windows"),
+        }
+    }
+}
 
-fn filter_canonical(kmers: Vec<String>) -> Vec<String> {
-    let revcomp_kmers: Vec<String> = kmers
+/// The k-mer classes for the given sizes, shortest first and without repeats.
+pub fn generate_kmer_maps(sizes: &[usize], canonical: bool) -> anyhow::Result<Vec<KmerMap>> {
+    if let Some(k) = sizes.iter().find(|&&k| k == 0 || k > MAX_K) {
+        anyhow::bail!("k-mer sizes must be from 1 to {MAX_K}, not {k}");
+    }
+    Ok(sizes
         .iter()
-        .map(|e| {
-            std::str::from_utf8(&reverse_complement(e.as_bytes()))
-                .unwrap()
-                .to_owned()
-        })
-        .collect();
+        .sorted()
+        .dedup()
+        .map(|&len| KmerMap { len, canonical })
+        .collect())
+}
+
+/// The 2-bit code of each byte, or 4 for anything that isn't ACGT.
+/// Lowercase bases are coded the same as uppercase.
+pub const ENCODE: [u8; 256] = build_encode();
+const fn build_encode() -> [u8; 256] {
+    let mut lut = [4u8; 256];
+    lut[b'A' as usize] = 0;
+    lut[b'a' as usize] = 0;
+    lut[b'C' as usize] = 1;
+    lut[b'c' as usize] = 1;
+    lut[b'G' as usize] = 2;
+    lut[b'g' as usize] = 2;
+    lut[b'T' as usize] = 3;
+    lut[b't' as usize] = 3;
+    lut
+}
+
+/// The k-mer with the given code.
+pub fn decode(code: usize, k: usize) -> String {
+    (0..k)
+        .rev()
+        .map(|i| b"ACGT"[(code >> (2 * i)) & 3] as char)
+        .collect()
+}
+
+/// The code of the reverse complement of the k-mer with this code.
+pub fn reverse_complement(code: usize, k: usize) -> usize {
+    // complementing is flipping both bits: A=00 <-> T=11, C=01 <-> G=10
+    let mut x = !(code as u64);
+    // then reverse the order of the bases, pairs within bytes first
+    x = ((x >> 2) & 0x3333_3333_3333_3333) | ((x & 0x3333_3333_3333_3333) << 2);
+    x = ((x >> 4) & 0x0f0f_0f0f_0f0f_0f0f) | ((x & 0x0f0f_0f0f_0f0f_0f0f) << 4);
+    (x.swap_bytes() >> (64 - 2 * k)) as usize
+}
 
-    let mut canonical_kmers = Vec::new();
+/// The code each k-mer is counted under. With `--canonical` a k-mer and
+/// its reverse complement share the smaller code, looked up in a table
+/// when there are few enough k-mers and worked out as needed when not.
+pub enum CodeMap {
+    Stranded,
+    Table(Vec<usize>),
+    Computed(usize),
+}
 
-    for (e1, e2) in kmers.into_iter().zip(revcomp_kmers.into_iter()) {
-        if e1 < e2 {
-            canonical_kmers.push(e1);
-        } else {
-            canonical_kmers.push(e2);
+impl CodeMap {
+    #[inline]
+    pub fn get(&self, code: usize) -> usize {
+        match self {
+            CodeMap::Stranded => code,
+            CodeMap::Table(table) => table[code],
+            CodeMap::Computed(k) => code.min(reverse_complement(code, *k)),
         }
     }
-    canonical_kmers.sort_unstable();
-    canonical_kmers.dedup();
-    canonical_kmers
 }
 
-// move out of kmeru8.rs
-fn gen_all_kmers(k: usize) -> Vec<String> {
-    let mut output: Vec<String> = Vec::new();
-    let nucleotides = ["A", "C", "G", "T"].iter().map(|x| x.to_string()).collect();
-    if k == 0 {
-        return output;
-    } else if k == 1 {
-        return nucleotides;
-    } else {
-        for suffix in gen_all_kmers(k - 1) {
-            for nt in &nucleotides {
-                let new = suffix.clone() + nt;
-                output.push(new);
+/// Call `f` with the code of each k-mer starting in `starts` that is all
+/// ACGT, in order, rolling the code along rather than re-encoding every
+/// k-mer. K-mers containing anything else are skipped.
+pub fn each_kmer(dna: &[u8], k: usize, starts: std::ops::Range<usize>, mut f: impl FnMut(usize)) {
+    if starts.is_empty() {
+        return;
+    }
+    // all ones for k = 32
+    let mask = 1usize
+        .checked_shl(2 * k as u32)
+        .map_or(usize::MAX, |bit| bit - 1);
+    let mut code = 0;
+    // how many ACGT bases in a row end at this one
+    let mut run = 0;
+    for &base in &dna[starts.start..starts.end + k - 1] {
+        match ENCODE[base as usize] {
+            4 => run = 0,
+            bits => {
+                code = ((code << 2) | bits as usize) & mask;
+                run = (run + 1).min(k);
             }
         }
+        if run == k {
+            f(code);
+        }
     }
-    output
 }
 
-// hacky display for Vec<i32>
+// tab separated display for the rows of counts
 #[derive(Clone)]
-pub struct WriteArray(pub Vec<i32>);
+pub struct WriteArray<'a, T>(pub &'a [T]);
 
-impl Display for WriteArray {
+impl<T: Display> Display for WriteArray<'_, T> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        let mut tab_separated = String::new();
+        write!(f, "{}", self.0.iter().join("\t"))
+    }
+}
 
-        for num in &self.0[0..self.0.len() - 1] {
-            tab_separated.push_str(&num.to_string());
-            tab_separated.push('\t');
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kmers_in_lexicographic_order() {
+        let kmers = KmerMap {
+            len: 2,
+            canonical: false,
         }
+        .kmers();
+        assert_eq!(kmers.len(), 16);
+        assert_eq!(kmers[..5], ["AA", "AC", "AG", "AT", "CA"]);
+        assert_eq!(kmers[6], "CG");
+        assert!(kmers.windows(2).all(|w| w[0] < w[1]));
+        let tetra = KmerMap {
+            len: 4,
+            canonical: false,
+        };
+        assert_eq!(tetra.kmers()[255], "TTTT");
+    }
+
+    #[test]
+    fn test_canonical_kmers() {
+        let canonical = |len| KmerMap {
+            len,
+            canonical: true,
+        };
+        assert_eq!(
+            canonical(2).kmers(),
+            ["AA", "AC", "AG", "AT", "CA", "CC", "CG", "GA", "GC", "TA"]
+        );
+        assert_eq!(canonical(3).kmers().len(), 32);
+        assert_eq!(canonical(4).kmers().len(), 136);
 
-        tab_separated.push_str(&self.0[self.0.len() - 1].to_string());
-        write!(f, "{}", tab_separated)
+        // the table and the computed codes agree
+        let CodeMap::Table(table) = canonical(4).code_map() else {
+            panic!("dense k-mers use a table");
+        };
+        let computed = CodeMap::Computed(4);
+        assert!((0..256).all(|code| table[code] == computed.get(code)));
+        assert_eq!(computed.get(0b11_11_11_10), 0b01_00_00_00); // TTTG -> CAAA
     }
-}
-// hacky display for Vec<Vec<u8>>
-// which is what the kmers are stored as for most of the time.
-#[derive(Clone)]
-pub struct WriteKmerValues<'a>(pub Vec<&'a Vec<u8>>);
 
-impl<'a> Display for WriteKmerValues<'a> {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        let mut tab_separated = String::new();
+    #[test]
+    fn test_reverse_complement() {
+        let rc = |kmer: &str| {
+            let mut code = 0;
+            each_kmer(kmer.as_bytes(), kmer.len(), 0..1, |c| code = c);
+            decode(reverse_complement(code, kmer.len()), kmer.len())
+        };
+        assert_eq!(rc("A"), "T");
+        assert_eq!(rc("ACG"), "CGT");
+        assert_eq!(rc("AACCGGTTA"), "TAACCGGTT");
+        let long = "ACGTTGCAAGGCTTACCGATCGATGCATGCAG";
+        let expected: String = long
+            .chars()
+            .rev()
+            .map(|b| match b {
+                'A' => 'T',
+                'C' => 'G',
+                'G' => 'C',
+                _ => 'A',
+            })
+            .collect();
+        assert_eq!(rc(long), expected);
+    }
 
-        for kmer in &self.0[0..self.0.len() - 1] {
-            let kmer_str = std::str::from_utf8(kmer).unwrap();
-            tab_separated.push_str(kmer_str);
-            tab_separated.push('\t');
-        }
+    #[test]
+    fn test_generate_kmer_maps() {
+        let lens = |sizes: &[usize]| {
+            generate_kmer_maps(sizes, false)
+                .unwrap()
+                .iter()
+                .map(|m| m.len)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lens(&DEFAULT_KMER_SIZES), [2, 3, 4]);
+        assert_eq!(lens(&[6, 1, 6, 2]), [1, 2, 6]);
+        assert!(generate_kmer_maps(&[0], false).is_err());
+        assert!(generate_kmer_maps(&[33], false).is_err());
+    }
+
+    #[test]
+    fn test_each_kmer_skips_non_acgt() {
+        let mut codes = Vec::new();
+        each_kmer(b"ACgTNACG", 3, 0..6, |code| codes.push(decode(code, 3)));
+        assert_eq!(codes, ["ACG", "CGT", "ACG"]);
+
+        // only k-mers starting in the range, even if more bases follow
+        let mut codes = Vec::new();
+        each_kmer(b"ACGTAC", 2, 2..4, |code| codes.push(decode(code, 2)));
+        assert_eq!(codes, ["GT", "TA"]);
 
-        tab_separated.push_str(std::str::from_utf8(self.0[self.0.len() - 1]).unwrap());
-        write!(f, "{}", tab_separated)
+        // the code of a 32-mer uses every bit
+        let mut codes = Vec::new();
+        each_kmer(&[b'T'; 33], 32, 0..2, |code| codes.push(code));
+        assert_eq!(codes, [usize::MAX, usize::MAX]);
     }
 }
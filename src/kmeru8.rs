@@ -1,122 +1,203 @@
-use crate::kmer_maps::KmerMap;
-use rayon::prelude::*;
-use std::sync::mpsc::channel;
-
-// calculating shannon diversity of di/tri/tetranucleotides
-// convenience struct
-pub struct KmerStats {
-    // the frequency distribution of the kmer
-    pub freq_dist_k: Vec<i32>,
-    // the length of the kmer
-    pub kmer_length: usize,
-    // the shannon diversity
+use std::collections::HashMap;
+
+use crate::kmer_maps::{self, each_kmer, CodeMap, KmerMap};
+use crate::windows::range_changes;
+
+/// The k-mers of one length in a window, and their Shannon diversity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KmerSpectrum {
+    pub kmer_map: KmerMap,
     pub shannon: f64,
+    // (code, count) of the k-mers present, in code order
+    pub counts: Vec<(usize, u32)>,
 }
 
-// punted to main.rs
-pub struct ShannonDiversity {
-    // of dinucleotides
-    pub dinucleotides: f64,
-    // of trinucleotides
-    pub trinucleotides: f64,
-    // of tetranucleotides
-    pub tetranucleotides: f64,
-    // the dimer frequency distribution
-    pub di_freq: Vec<i32>,
-    // the trimer frequency distribution
-    pub tri_freq: Vec<i32>,
-    // the tetramer frequency distribution
-    pub tetra_freq: Vec<i32>,
+impl KmerSpectrum {
+    /// The count of the k-mer with this code, together with its reverse
+    /// complement if the k-mers are canonical.
+    pub fn count(&self, code: usize) -> u32 {
+        let code = canonical_code(&self.kmer_map, code);
+        match self.counts.binary_search_by_key(&code, |&(c, _)| c) {
+            Ok(i) => self.counts[i].1,
+            Err(_) => 0,
+        }
+    }
+
+    /// The count of every k-mer in `KmerMap::codes` order, zeros included.
+    pub fn dense(&self) -> Vec<u32> {
+        let mut dense = vec![0; self.kmer_map.size()];
+        for &(code, count) in &self.counts {
+            dense[code] = count;
+        }
+        match self.kmer_map.canonical {
+            true => self.kmer_map.codes().iter().map(|&c| dense[c]).collect(),
+            false => dense,
+        }
+    }
 }
 
-pub fn kmer_diversity(dna: &[u8], kmer_maps: Vec<KmerMap>) -> ShannonDiversity {
-    // parallel iterate over 2-4mers
-    let (sender, receiver) = channel();
-    kmer_maps.into_par_iter().for_each_with(sender, |s, i| {
-        // need to do di/tri/tetranucleotides
-        // generate all the kmers in a window
-        let kmers = dna.windows(i.len);
-        let mut map = i.map;
-
-        // iterate over sliding windows of length k
-        for kmer in kmers {
-            // kmer to upper
-            // unfortunately this creates a copy
-            // but in place manipulation seems difficult, because rust.
-            let kmer_upper = kmer.to_ascii_uppercase();
-            if kmer_upper.contains(&b'N') {
-                continue;
-            }
-            let count = map.entry(kmer_upper).or_insert(0);
-            *count += 1;
+// the code a k-mer is counted under
+fn canonical_code(kmer_map: &KmerMap, code: usize) -> usize {
+    match kmer_map.canonical {
+        true => code.min(kmer_maps::reverse_complement(code, kmer_map.len)),
+        false => code,
+    }
+}
+
+pub fn kmer_diversity(dna: &[u8], kmer_maps: &[KmerMap]) -> Vec<KmerSpectrum> {
+    let mut kmers = SlidingKmers::new(kmer_maps);
+    kmers.slide(dna, (0, dna.len()));
+    kmers.spectra()
+}
+
+// k-mer counts by code: a flat array when every k-mer can have a slot,
+// and just the k-mers present when there are too many possible ones.
+#[derive(Clone)]
+enum Counter {
+    Dense(Vec<u32>),
+    Sparse(HashMap<usize, u32>),
+}
+
+impl Counter {
+    fn new(kmer_map: &KmerMap) -> Self {
+        match kmer_map.is_dense() {
+            true => Counter::Dense(vec![0; kmer_map.size()]),
+            false => Counter::Sparse(HashMap::new()),
         }
-        // now calculate shannon diversity
-        // is this right??
-        let shannon = shannon_diversity(map.values().cloned().collect());
-
-        // save the hashmap here too.
-        // HashMap -> Vec -> sorted Vec by HashMap keys
-        // so keys should always be in the same order.
-        let mut map_vec: Vec<_> = map.into_iter().collect();
-        map_vec.sort_by(|x, y| x.0.cmp(&y.0));
-        let values: Vec<i32> = map_vec.iter().map(|(_x, y)| *y).collect();
-
-        s.send(KmerStats {
-            freq_dist_k: values,
-            kmer_length: i.len,
-            shannon,
-        })
-        .expect("KmerStats did not send!");
-    });
-    // collect stats
-    let kmer_stats: Vec<KmerStats> = receiver.iter().collect();
-
-    // decompose into separate shannon indices
-    // and the k-mer freq spectra
-    // TODO: is there a better way to do this?
-    let mut dinucleotides: f64 = 0.0;
-    let mut trinucleotides: f64 = 0.0;
-    let mut tetranucleotides: f64 = 0.0;
-    let mut divalues: Vec<i32> = Vec::new();
-    let mut trivalues: Vec<i32> = Vec::new();
-    let mut tetravalues: Vec<i32> = Vec::new();
-
-    for stat in kmer_stats {
-        match stat.kmer_length {
-            2usize => {
-                dinucleotides += stat.shannon;
-                divalues = stat.freq_dist_k;
+    }
+
+    #[inline]
+    fn update(&mut self, code: usize, delta: i32) {
+        match self {
+            Counter::Dense(counts) => counts[code] = counts[code].wrapping_add_signed(delta),
+            Counter::Sparse(counts) => {
+                let count = counts.entry(code).or_default();
+                *count = count.wrapping_add_signed(delta);
+                // keep the map as small as the window
+                if *count == 0 {
+                    counts.remove(&code);
+                }
             }
-            3usize => {
-                trinucleotides += stat.shannon;
-                trivalues = stat.freq_dist_k;
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Counter::Dense(counts) => counts.fill(0),
+            Counter::Sparse(counts) => counts.clear(),
+        }
+    }
+
+    // the k-mers present, in code order
+    fn present(&self) -> Vec<(usize, u32)> {
+        match self {
+            Counter::Dense(counts) => counts
+                .iter()
+                .enumerate()
+                .filter(|(_, &count)| count > 0)
+                .map(|(code, &count)| (code, count))
+                .collect(),
+            Counter::Sparse(counts) => {
+                let mut present: Vec<_> = counts.iter().map(|(&c, &n)| (c, n)).collect();
+                present.sort_unstable();
+                present
             }
-            4usize => {
-                tetranucleotides += stat.shannon;
-                tetravalues = stat.freq_dist_k;
+        }
+    }
+}
+
+// k-mer counts for a window that slides along a sequence. Only the
+// k-mers entering and leaving the window are touched, rather than
+// recounting the whole window each time.
+pub struct SlidingKmers {
+    maps: Vec<KmerMap>,
+    // for each k, the code each k-mer is counted under
+    code_maps: Vec<CodeMap>,
+    // for each k, the count of every k-mer by its 2-bit code
+    counters: Vec<Counter>,
+    // for each k, the range of k-mer start positions currently counted
+    ranges: Vec<(usize, usize)>,
+}
+
+impl SlidingKmers {
+    pub fn new(kmer_maps: &[KmerMap]) -> Self {
+        Self {
+            maps: kmer_maps.to_vec(),
+            code_maps: kmer_maps.iter().map(KmerMap::code_map).collect(),
+            counters: kmer_maps.iter().map(Counter::new).collect(),
+            ranges: vec![(0, 0); kmer_maps.len()],
+        }
+    }
+
+    // move the counts to the half-open window `window` of `dna`.
+    pub fn slide(&mut self, dna: &[u8], window: (usize, usize)) {
+        let (start, end) = window;
+        for (((kmer_map, code_map), counter), range) in self
+            .maps
+            .iter()
+            .zip(&self.code_maps)
+            .zip(self.counters.iter_mut())
+            .zip(self.ranges.iter_mut())
+        {
+            let k = kmer_map.len;
+            // k-mers which fit entirely inside the window
+            let new_range = (start, (end + 1).saturating_sub(k).max(start));
+
+            match range_changes(*range, new_range) {
+                Some(changes) => {
+                    for (starts, delta) in changes {
+                        each_kmer(dna, k, starts, |code| {
+                            counter.update(code_map.get(code), delta)
+                        });
+                    }
+                }
+                None => {
+                    counter.clear();
+                    each_kmer(dna, k, new_range.0..new_range.1, |code| {
+                        counter.update(code_map.get(code), 1)
+                    });
+                }
             }
-            _ => (),
+            *range = new_range;
         }
     }
 
-    ShannonDiversity {
-        dinucleotides,
-        trinucleotides,
-        tetranucleotides,
-        di_freq: divalues,
-        tri_freq: trivalues,
-        tetra_freq: tetravalues,
+    /// The count of one k-mer in the current window, if its length is counted.
+    pub fn count(&self, k: usize, code: usize) -> Option<u32> {
+        let i = self.maps.iter().position(|m| m.len == k)?;
+        let code = canonical_code(&self.maps[i], code);
+        Some(match &self.counters[i] {
+            Counter::Dense(counts) => counts[code],
+            Counter::Sparse(counts) => counts.get(&code).copied().unwrap_or(0),
+        })
+    }
+
+    // the shannon diversity and frequency spectrum of each k in the current window
+    pub fn spectra(&self) -> Vec<KmerSpectrum> {
+        self.maps
+            .iter()
+            .zip(&self.counters)
+            .map(|(kmer_map, counter)| {
+                // in code order, so lexicographic order
+                let counts = counter.present();
+                KmerSpectrum {
+                    kmer_map: *kmer_map,
+                    shannon: shannon_diversity(counts.iter().map(|&(_, count)| count as u64)),
+                    counts,
+                }
+            })
+            .collect()
     }
 }
 
 // using the natural log
-fn shannon_diversity(vec: Vec<i32>) -> f64 {
+pub(crate) fn shannon_diversity(counts: impl Iterator<Item = u64> + Clone) -> f64 {
     // sum elements to get proportions
-    let vec_sum: i32 = vec.iter().sum();
+    let total: u64 = counts.clone().sum();
     let mut diversity = 0f64;
 
-    for count in vec.iter().filter(|count| **count > 0i32) {
-        let probability = *count as f64 / (vec_sum as f64);
+    for count in counts.filter(|count| *count > 0) {
+        let probability = count as f64 / (total as f64);
         diversity -= probability * probability.log2();
     }
     diversity
@@ -261,10 +342,8 @@ pub fn ctw_bits_per_base_dna(dna: &[u8], max_depth: usize) -> f64 {
 
                 // 3) children product (sum of logs)
                 let mut sum_children_log_w = 0.0;
-                for ch in self.children.iter() {
-                    if let Some(ref child) = ch {
-                        sum_children_log_w += child.log_w;
-                    }
+                for child in self.children.iter().flatten() {
+                    sum_children_log_w += child.log_w;
                 }
 
                 // 4) mixture
@@ -361,7 +440,73 @@ mod tests {
 
     #[test]
     fn test_shannon_entropy() {
-        todo!()
+        // four equally common kmers -> 2 bits
+        let even = shannon_diversity([5, 5, 5, 5].into_iter());
+        assert_almost_eq(even, 2.0, 1e-12);
+
+        // a single kmer class carries no information
+        let single = shannon_diversity([0, 12, 0, 0].into_iter());
+        assert_almost_eq(single, 0.0, 1e-12);
+
+        // zero counts are ignored rather than producing NaN
+        let skewed = shannon_diversity([2, 2, 0, 0].into_iter());
+        assert_almost_eq(skewed, 1.0, 1e-12);
+    }
+
+    #[test]
+    fn test_sliding_kmers_match_fresh() {
+        // dense and sparse counting
+        let kmer_maps = crate::kmer_maps::generate_kmer_maps(&[1, 2, 4, 7], false).unwrap();
+        let dna = b"ACGTNacgtTTGCAAWGGCCATNNATCGGAC";
+        let mut sliding = SlidingKmers::new(&kmer_maps);
+        // overlapping, shrinking at the end, and jumping past the last window
+        for window in [(0, 12), (3, 15), (9, 21), (20, 31), (25, 31), (0, 4)] {
+            sliding.slide(dna, window);
+            let fresh = kmer_diversity(&dna[window.0..window.1], &kmer_maps);
+            assert_eq!(sliding.spectra(), fresh);
+        }
+    }
+
+    #[test]
+    fn test_spectrum_counts() {
+        let kmer_maps = crate::kmer_maps::generate_kmer_maps(&[2], false).unwrap();
+        let [spectrum] = &kmer_diversity(b"CGACG", &kmer_maps)[..] else {
+            panic!("one k was counted");
+        };
+        // CG twice, GA and AC once
+        assert_eq!(spectrum.counts, [(1, 1), (6, 2), (8, 1)]);
+        assert_eq!(spectrum.count(6), 2);
+        assert_eq!(spectrum.count(0), 0);
+        assert_eq!(spectrum.dense()[6], 2);
+        assert_eq!(spectrum.dense().len(), 16);
+    }
+
+    #[test]
+    fn test_canonical_counts() {
+        let kmer_maps = crate::kmer_maps::generate_kmer_maps(&[2, 3, 8], true).unwrap();
+        let dna = b"AACCTTGGACGTTAGCNNAGCTTTGCAAACGG";
+        let forward = kmer_diversity(dna, &kmer_maps);
+        // the same either way round
+        assert_eq!(
+            forward,
+            kmer_diversity(&reverse_complement(dna), &kmer_maps)
+        );
+
+        // GT is counted as AC, and CG with itself
+        let dinucs = &forward[0];
+        assert_eq!(dinucs.count(0b10_11), dinucs.count(0b00_01));
+        assert_eq!(dinucs.count(0b00_01), 4);
+        assert_eq!(dinucs.count(0b01_10), 2);
+        assert_eq!(dinucs.dense().len(), 10);
+
+        // each strand's k-mers add up to the canonical counts
+        let stranded = kmer_diversity(
+            dna,
+            &crate::kmer_maps::generate_kmer_maps(&[3], false).unwrap(),
+        );
+        let total = |spectrum: &KmerSpectrum| spectrum.counts.iter().map(|(_, n)| n).sum::<u32>();
+        assert_eq!(total(&forward[1]), total(&stranded[0]));
+        assert!(forward[1].counts.len() < stranded[0].counts.len());
     }
 
     // --- CTW tests ---------------------------------------------------------
@@ -482,9 +627,10 @@ mod tests {
             "context should not hurt for mono-base"
         );
 
-        // Balanced i.i.d.-looking string should be around ~2 bits/base (slightly > because KT)
-        let iid = b"ACGTACGTACGTACGTACGTACGTACGTACGT";
-        let bpb_iid_d0 = super::ctw_bits_per_base_dna(iid, 0);
+        // Balanced i.i.d.-looking string should be around ~2 bits/base (slightly > because KT).
+        // The KT overhead shrinks with length, so use enough bases to get under the bound.
+        let iid = b"ACGT".repeat(32);
+        let bpb_iid_d0 = super::ctw_bits_per_base_dna(&iid, 0);
         assert!(
             bpb_iid_d0 > 1.5 && bpb_iid_d0 < 2.2,
             "expected near-2 bits for balanced iid-like string, got {bpb_iid_d0}"
@@ -1,6 +1,30 @@
+pub mod bigwig;
+pub mod columnar;
+pub mod complexity;
+pub mod config;
 #[cfg(feature = "entropy")]
 pub mod entropy;
+pub mod error;
+pub mod fai;
 pub mod fw;
+pub mod gaps;
+pub mod input;
+pub mod islands;
 pub mod kmer_maps;
 pub mod kmeru8;
+pub mod masked;
+pub mod microsatellites;
+pub mod motifs;
+pub mod outpath;
+pub mod pipeline;
+pub mod progress;
+pub mod reader;
+pub mod region;
+pub mod runs;
 pub mod seq_statsu8;
+pub mod sort;
+pub mod summary;
+pub mod tandem;
+pub mod telomeres;
+pub mod tracks;
+pub mod windows;
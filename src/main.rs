@@ -1,15 +1,33 @@
 // Max Brown, Matthieu Muffato, and Rich Challis 2023
 // Wellcome Sanger Institute
 
-use std::fs::{create_dir_all, File};
+use std::fs::File;
 use std::io::BufWriter;
 use std::path::PathBuf;
+use std::process::ExitCode;
 
 use anyhow::{Context, Result};
 use clap::{crate_authors, value_parser, Arg, ArgAction, Command};
-use fasta_windows::fw::fasta_windows;
+use fasta_windows::config::Config;
+use fasta_windows::fai::Fai;
+use fasta_windows::fw::{fasta_windows, Sink};
+use fasta_windows::input::Input;
+use fasta_windows::outpath::{Format, OutputPrefix};
+use fasta_windows::sort::SortOrder;
+use fasta_windows::tracks::TrackFormat;
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
+    // a single line for anything that goes wrong, with what caused it
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("[-]\t{e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<()> {
     let cmd = Command::new("Fasta windows")
         .version(clap::crate_version!())
         .arg_required_else_help(true)
@@ -22,7 +40,7 @@ fn main() -> Result<()> {
                 .num_args(1)
                 .required(true)
                 .value_parser(value_parser!(PathBuf))
-                .help("The input fasta file."),
+                .help("The input fasta file, optionally compressed (gzip, bgzip, bzip2, xz or zstd). Use - to read from stdin."),
         )
         .arg(
             Arg::new("window_size")
@@ -33,6 +51,14 @@ fn main() -> Result<()> {
                 .value_parser(value_parser!(usize))
                 .default_value("1000"),
         )
+        .arg(
+            Arg::new("step")
+                .short('s')
+                .long("step")
+                .help("Start a window every <step> bases, giving overlapping windows if smaller than the window size. [default: window_size]")
+                .num_args(1)
+                .value_parser(value_parser!(usize)),
+        )
         .arg(
             Arg::new("description")
                 .short('d')
@@ -44,11 +70,46 @@ fn main() -> Result<()> {
             Arg::new("output")
                 .short('o')
                 .long("output")
-                .help("Output filename for the TSV's (without extension).")
+                .help("Output filename prefix for the TSV's (without extension). May include directories, which are created inside --outdir if it's given, or used as the prefix as they are if not.")
                 .value_parser(value_parser!(PathBuf))
                 .num_args(1)
                 .required(true),
         )
+        .arg(
+            Arg::new("outdir")
+                .long("outdir")
+                .help("Directory the output files are written to [default: fw_out, unless --output has directories of its own]. Ignored if --output is an absolute path.")
+                .value_parser(value_parser!(PathBuf))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .help("Overwrite existing output files. By default fasta_windows refuses to."),
+        )
+        .arg(
+            Arg::new("region")
+                .short('r')
+                .long("region")
+                .num_args(1)
+                .action(ArgAction::Append)
+                .help("Only compute windows over this region, samtools style: chr, chr:start or chr:start-end (1-based, inclusive). May be given more than once."),
+        )
+        .arg(
+            Arg::new("regions_bed")
+                .long("regions-bed")
+                .num_args(1)
+                .value_parser(value_parser!(PathBuf))
+                .help("Only compute windows over the intervals in this BED file. Uses the .fai index for random access if the fasta is uncompressed and has one."),
+        )
+        .arg(
+            Arg::new("write_fai")
+                .long("write-fai")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["region", "regions_bed"])
+                .help("Write a samtools-compatible .fai index next to the (uncompressed) fasta while reading it, for later runs to use."),
+        )
         .arg(
             Arg::new("masked")
                 .short('m')
@@ -56,6 +117,230 @@ fn main() -> Result<()> {
                 .action(ArgAction::SetTrue)
                 .help("Consider only uppercase nucleotides in the calculations."),
         )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .num_args(1)
+                .value_parser(SortOrder::NAMES)
+                .help("Sort sequences in the output instead of keeping the input FASTA order. `length` puts the longest sequence first."),
+        )
+        .arg(
+            Arg::new("kmer_sizes")
+                .short('k')
+                .long("kmer-sizes")
+                .num_args(1)
+                .value_delimiter(',')
+                .value_parser(value_parser!(usize))
+                .default_value("2,3,4")
+                .help("Comma separated k-mer sizes to count in each window, from 1 to 32. Each gets a Shannon diversity column and a file of counts, with a column per k-mer up to k=6 and a row per k-mer present above that."),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .num_args(1)
+                .value_parser(Format::NAMES)
+                .default_value("tsv")
+                .help("The format of the window statistics and k-mer count files. `parquet` and `arrow` (Arrow IPC) have typed columns, and are much faster to load into dataframes."),
+        )
+        .arg(
+            Arg::new("tracks")
+                .long("tracks")
+                .num_args(1)
+                .value_delimiter(',')
+                .help("Comma separated statistics to also write as genome browser tracks, named as their columns in the windows file, e.g. GC_prop,GC_skew,Shannon_entropy,ctw."),
+        )
+        .arg(
+            Arg::new("track_format")
+                .long("track-format")
+                .num_args(1)
+                .value_delimiter(',')
+                .value_parser(TrackFormat::NAMES)
+                .default_value("bedgraph")
+                .help("The format of the --tracks files: bedgraph, bigwig or both (bedgraph,bigwig). bigWigs are written directly, with zoom levels, so no UCSC tools are needed."),
+        )
+        .arg(
+            Arg::new("rho")
+                .long("rho")
+                .action(ArgAction::SetTrue)
+                .help("Also write _rho_windows.tsv, Karlin's dinucleotide relative abundance rho(XY) = f(XY)/(f(X)f(Y)) in each window, for all 16 dinucleotides (or the 10 symmetrised ones with --canonical)."),
+        )
+        .arg(
+            Arg::new("motifs")
+                .long("motifs")
+                .num_args(1)
+                .value_parser(value_parser!(PathBuf))
+                .help("Also write _motif_windows.tsv, the number of matches of each motif in this file in each window, on either strand. Each line is a name and a pattern, which may have IUPAC codes, e.g. `HinfI GANTC`."),
+        )
+        .arg(
+            Arg::new("overlapping_motifs")
+                .long("overlapping-motifs")
+                .action(ArgAction::SetTrue)
+                .requires("motifs")
+                .help("Count every match of a motif, including those overlapping the match before."),
+        )
+        .arg(
+            Arg::new("tandem_repeats")
+                .long("tandem-repeats")
+                .action(ArgAction::SetTrue)
+                .help("Also write _tandem_windows.tsv, the period of the dominant tandem repeat in each window, the proportion of the window it covers, and its consensus repeat unit."),
+        )
+        .arg(
+            Arg::new("gaps")
+                .long("gaps")
+                .action(ArgAction::SetTrue)
+                .help("Also write _gaps.bed, every run of Ns at least --min-gap long, and add the number of gaps and gap bases in each window to the windows file."),
+        )
+        .arg(
+            Arg::new("min_gap")
+                .long("min-gap")
+                .num_args(1)
+                .value_parser(value_parser!(usize))
+                .default_value("1")
+                .help("The shortest run of Ns that is a gap, for --gaps and --agp. Shorter runs are treated as ambiguous bases."),
+        )
+        .arg(
+            Arg::new("agp")
+                .long("agp")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["region", "regions_bed"])
+                .help("Also write _assembly.agp, describing each sequence as contigs separated by gaps (AGP 2.1)."),
+        )
+        .arg(
+            Arg::new("masked_runs")
+                .long("masked-runs")
+                .action(ArgAction::SetTrue)
+                .help("Also write _masked.bed, every run of soft-masked (lowercase) bases, and add the number of runs and their longest and mean length in each window to the windows file."),
+        )
+        .arg(
+            Arg::new("strs")
+                .long("strs")
+                .action(ArgAction::SetTrue)
+                .help("Add the longest homopolymer, the number of homopolymers at least --min-homopolymer long, and the proportion in short tandem repeats (1-6bp units) of each window to the windows file."),
+        )
+        .arg(
+            Arg::new("str_bed")
+                .long("str-bed")
+                .action(ArgAction::SetTrue)
+                .help("Also write _strs.bed, every short tandem repeat with at least --str-min-copies copies of its unit, with the unit and number of copies."),
+        )
+        .arg(
+            Arg::new("min_homopolymer")
+                .long("min-homopolymer")
+                .num_args(1)
+                .value_parser(value_parser!(usize))
+                .default_value("5")
+                .help("The shortest homopolymer counted in each window, for --strs."),
+        )
+        .arg(
+            Arg::new("str_min_copies")
+                .long("str-min-copies")
+                .num_args(1)
+                .value_delimiter(',')
+                .value_parser(value_parser!(usize))
+                .default_value("12,7,5,4,4,4")
+                .help("The fewest copies of a 1, 2, 3, 4, 5 and 6bp unit that make a short tandem repeat, for --strs and --str-bed."),
+        )
+        .arg(
+            Arg::new("complexity")
+                .long("complexity")
+                .action(ArgAction::SetTrue)
+                .help("Add the DUST score and Wootton-Federhen complexity of each window to the windows file (or the entropy BED)."),
+        )
+        .arg(
+            Arg::new("dust_bed")
+                .long("dust-bed")
+                .action(ArgAction::SetTrue)
+                .help("Also write _dust.bed, the low-complexity intervals masked by symmetric DUST, as by dustmasker and minimap2."),
+        )
+        .arg(
+            Arg::new("dust_window")
+                .long("dust-window")
+                .num_args(1)
+                .value_parser(value_parser!(usize))
+                .default_value("64")
+                .help("The longest interval DUST masks, for --dust-bed."),
+        )
+        .arg(
+            Arg::new("dust_threshold")
+                .long("dust-threshold")
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("20")
+                .help("DUST masks intervals scoring above this, in tenths, for --dust-bed."),
+        )
+        .arg(
+            Arg::new("summary")
+                .long("summary")
+                .action(ArgAction::SetTrue)
+                .help("Also write _summary.tsv and _summary.json: length, GC, skews, N and masked proportions, CpGs, gaps and k-mer diversity over each whole sequence, and for the genome with its N50 and N90."),
+        )
+        .arg(
+            Arg::new("canonical")
+                .long("canonical")
+                .action(ArgAction::SetTrue)
+                .help("Count each k-mer together with its reverse complement, so the counts and Shannon diversities don't depend on the strand. Only the lexicographically smaller of each pair gets a column (e.g. 136 tetranucleotides)."),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .num_args(1)
+                .value_parser(value_parser!(usize))
+                .default_value("0")
+                .help("Number of threads to use. 0 uses all available cores."),
+        )
+        .arg(
+            Arg::new("cpg_islands")
+                .long("cpg-islands")
+                .action(ArgAction::SetTrue)
+                .help("CpG island mode: output a single BED file of CpG islands, with their GC proportion, CpG o/e and number of CpGs. No window statistics are computed."),
+        )
+        .arg(
+            Arg::new("island_min_length")
+                .long("island-min-length")
+                .num_args(1)
+                .value_parser(value_parser!(usize))
+                .default_value("200")
+                .help("The window scanned for CpG islands, and so the shortest island."),
+        )
+        .arg(
+            Arg::new("island_min_gc")
+                .long("island-min-gc")
+                .num_args(1)
+                .value_parser(value_parser!(f32))
+                .default_value("0.5")
+                .help("A CpG island window must have a GC proportion above this."),
+        )
+        .arg(
+            Arg::new("island_min_oe")
+                .long("island-min-oe")
+                .num_args(1)
+                .value_parser(value_parser!(f32))
+                .default_value("0.6")
+                .help("A CpG island window must have a CpG observed/expected ratio above this."),
+        )
+        .arg(
+            Arg::new("island_merge_gap")
+                .long("island-merge-gap")
+                .num_args(1)
+                .value_parser(value_parser!(usize))
+                .default_value("0")
+                .help("Join CpG islands that are no more than this many bases apart."),
+        )
+        .arg(
+            Arg::new("telomeres")
+                .long("telomeres")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("cpg_islands")
+                .help("Telomere mode: count the telomeric motif on both strands in each window, in _telomere_windows.tsv, and report the telomeric arrays at the ends of each sequence in _telomeres.tsv. No other statistics are computed."),
+        )
+        .arg(
+            Arg::new("telomere_motif")
+                .long("telomere-motif")
+                .num_args(1)
+                .default_value("vertebrate")
+                .help("The telomeric repeat, as it reads on the 3' end: vertebrate (TTAGGG), insect (TTAGG), plant (TTTAGGG), nematode (TTAGGC), or any motif of A, C, G and T."),
+        )
         .arg(
             Arg::new("ctw")
                 .short('c')
@@ -70,6 +355,7 @@ fn main() -> Result<()> {
             .short('e')
             .long("entropy")
             .action(ArgAction::SetTrue)
+            .conflicts_with_all(["cpg_islands", "telomeres"])
             .help(
                 "Entropy mode: output a single BED file of Shannon entropy per window. \
                  Skips all k-mer and nucleotide-composition computation for maximum speed.",
@@ -78,43 +364,129 @@ fn main() -> Result<()> {
 
     let matches = cmd.get_matches();
 
+    // the window statistics within each sequence, as well as the sequences
+    // themselves, are computed on this pool
+    let threads = *matches
+        .get_one::<usize>("threads")
+        .expect("defaulted by clap");
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+        .context("could not start the thread pool")?;
+
     let output = matches
         .get_one::<PathBuf>("output")
-        .context("Could not find output in CLI")?
-        .display();
+        .context("Could not find output in CLI")?;
+    let outdir = matches.get_one::<PathBuf>("outdir").map(PathBuf::as_path);
+    let prefix = OutputPrefix::new(outdir, output, matches.get_flag("force"));
 
-    if let Err(e) = create_dir_all("./fw_out/") {
-        eprintln!("[-]\tCreate directory error: {}", e);
-    }
+    let config = Config::from_args(&matches)?;
 
     #[cfg(feature = "entropy")]
     if matches.get_flag("entropy") {
         use fasta_windows::entropy::entropy_windows;
-        let bed_file = BufWriter::new(File::create(format!("./fw_out/{output}_entropy.bed"))?);
-        return entropy_windows(&matches, bed_file);
+        let mut suffixes = vec!["entropy.bed"];
+        if config.dust_bed {
+            suffixes.push("dust.bed");
+        }
+        run_with_outputs(&matches, &prefix, &suffixes, |input, files| {
+            let mut files = files.into_iter();
+            let bed = files.next().expect("one file per suffix");
+            entropy_windows(&config, input, bed, files.next())
+        })?;
+        match config.dust_bed {
+            true => eprintln!("[+]\tOutput written with prefix: {prefix}_"),
+            false => eprintln!(
+                "[+]\tOutput written to: {}",
+                prefix.path("entropy.bed").display()
+            ),
+        }
+        report_index(&matches);
+        return Ok(());
     }
 
-    let window_file_0 =
-        BufWriter::new(File::create(format!("./fw_out/{output}_freq_windows.tsv"))?);
-    let window_file_1 = BufWriter::new(File::create(format!(
-        "./fw_out/{output}_mononuc_windows.tsv"
-    ))?);
-    let window_file_2 = BufWriter::new(File::create(format!(
-        "./fw_out/{output}_dinuc_windows.tsv"
-    ))?);
-    let window_file_3 = BufWriter::new(File::create(format!(
-        "./fw_out/{output}_trinuc_windows.tsv"
-    ))?);
-    let window_file_4 = BufWriter::new(File::create(format!(
-        "./fw_out/{output}_tetranuc_windows.tsv"
-    ))?);
-
-    fasta_windows(
+    if matches.get_flag("cpg_islands") {
+        use fasta_windows::islands::cpg_island_windows;
+        run_with_outputs(
+            &matches,
+            &prefix,
+            &["cpg_islands.bed"],
+            |input, mut files| {
+                let bed = files.pop().expect("one file per suffix");
+                cpg_island_windows(&config, input, bed)
+            },
+        )?;
+        eprintln!(
+            "[+]\tOutput written to: {}",
+            prefix.path("cpg_islands.bed").display()
+        );
+        report_index(&matches);
+        return Ok(());
+    }
+
+    if matches.get_flag("telomeres") {
+        use fasta_windows::telomeres::telomere_windows;
+        let suffixes = ["telomere_windows.tsv", "telomeres.tsv"];
+        run_with_outputs(&matches, &prefix, &suffixes, |input, files| {
+            let mut files = files.into_iter();
+            let windows = files.next().expect("one file per suffix");
+            let report = files.next().expect("one file per suffix");
+            telomere_windows(&config, input, windows, report)
+        })?;
+        eprintln!("[+]\tOutput written with prefix: {prefix}_");
+        report_index(&matches);
+        return Ok(());
+    }
+
+    run_with_outputs(
         &matches,
-        window_file_0,
-        window_file_1,
-        window_file_2,
-        window_file_3,
-        window_file_4,
-    )
+        &prefix,
+        &config.output_suffixes(),
+        |input, files| {
+            fasta_windows(
+                &config,
+                input,
+                files.into_iter().map(Sink::seekable).collect(),
+            )
+        },
+    )?;
+    eprintln!("[+]\tOutput written with prefix: {prefix}_");
+    report_index(&matches);
+
+    Ok(())
+}
+
+// Open the input, with any regions and index, only once none of the
+// outputs turn out to exist already, and create the outputs only once it's
+// open, so a wrong path leaves nothing behind. If the run fails, the
+// outputs and any index it started are removed, so it can be run again
+// as it was.
+fn run_with_outputs<S: AsRef<str>>(
+    matches: &clap::ArgMatches,
+    prefix: &OutputPrefix,
+    suffixes: &[S],
+    run: impl FnOnce(Input, Vec<BufWriter<File>>) -> Result<()>,
+) -> Result<()> {
+    prefix.check(suffixes)?;
+    let input = Input::from_args(matches)?;
+    let result = prefix.create(suffixes).and_then(|files| run(input, files));
+    if result.is_err() {
+        prefix.remove(suffixes);
+        if matches.get_flag("write_fai") {
+            let fasta = matches
+                .get_one::<PathBuf>("fasta")
+                .expect("handled by clap");
+            let _ = std::fs::remove_file(Fai::path_for(fasta));
+        }
+    }
+    result
+}
+
+fn report_index(matches: &clap::ArgMatches) {
+    if matches.get_flag("write_fai") {
+        let fasta = matches
+            .get_one::<PathBuf>("fasta")
+            .expect("handled by clap");
+        eprintln!("[+]\tIndex written to: {}", Fai::path_for(fasta).display());
+    }
 }
@@ -0,0 +1,51 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::fw::{Entry, RecordWindows, WriteWindows};
+use crate::runs;
+
+/// The runs of soft-masked (lowercase) bases in `seq`, as half-open ranges.
+pub fn find_masked_runs(seq: &[u8]) -> Vec<(usize, usize)> {
+    runs::find_runs(seq, 1, |b| b.is_ascii_lowercase())
+}
+
+/// Set the number of masked runs touching each window, and the longest and
+/// mean length of them within it. The windows and runs are both in order
+/// along the sequence.
+pub fn count_window_masked_runs(entries: &mut [Entry], masked_runs: &[(usize, usize)]) {
+    for entry in entries {
+        let overlaps: Vec<usize> = runs::overlaps(masked_runs, (entry.start, entry.end)).collect();
+        entry.masked_runs = overlaps.len();
+        entry.longest_masked_run = overlaps.iter().copied().max().unwrap_or(0);
+        entry.mean_masked_run = match overlaps.is_empty() {
+            true => 0.0,
+            false => overlaps.iter().sum::<usize>() as f32 / overlaps.len() as f32,
+        };
+    }
+}
+
+/// The soft-masked runs of each sequence, as a BED of `ID start end length`.
+pub struct MaskedRuns<W: Write> {
+    bed: W,
+}
+
+impl<W: Write> MaskedRuns<W> {
+    pub fn new(bed: W) -> Self {
+        Self { bed }
+    }
+}
+
+impl<W: Write> WriteWindows for MaskedRuns<W> {
+    fn write_record(&mut self, record: &RecordWindows) -> Result<()> {
+        for &(start, end) in record.masked_runs.as_deref().unwrap_or_default() {
+            writeln!(self.bed, "{}\t{start}\t{end}\t{}", record.id, end - start)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.bed.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::fw::{Entry, RecordWindows, WriteWindows};
+use crate::kmer_maps::ENCODE;
+use crate::runs;
+
+/// The fewest copies of a 1 to 6bp unit that make a short tandem repeat,
+/// unless told otherwise.
+pub const DEFAULT_STR_MIN_COPIES: [usize; 6] = [12, 7, 5, 4, 4, 4];
+
+/// A short tandem repeat: a perfect run of copies of a 1 to 6bp unit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StrLocus {
+    pub start: usize,
+    pub end: usize,
+    pub unit: String,
+}
+
+impl StrLocus {
+    /// The number of copies of the unit, including any part copy at the end.
+    pub fn copies(&self) -> f64 {
+        (self.end - self.start) as f64 / self.unit.len() as f64
+    }
+}
+
+/// The longest run of the same base in `win`, and the number of runs at
+/// least `min_len` long. Runs are cut at the window's edges.
+pub fn window_homopolymers(win: &[u8], min_len: usize) -> (usize, usize) {
+    let (mut longest, mut count) = (0, 0);
+    let mut start = 0;
+    for i in 1..=win.len() {
+        let base = ENCODE[win[start] as usize];
+        if i == win.len() || base == 4 || ENCODE[win[i] as usize] != base {
+            if base != 4 {
+                longest = longest.max(i - start);
+                count += (i - start >= min_len) as usize;
+            }
+            start = i;
+        }
+    }
+    (longest, count)
+}
+
+// whether a unit isn't itself copies of a shorter unit
+fn is_primitive(unit: &[u8]) -> bool {
+    (1..unit.len())
+        .filter(|&d| unit.len().is_multiple_of(d))
+        .all(|d| (d..unit.len()).any(|i| unit[i] != unit[i - d]))
+}
+
+/// The perfect short tandem repeats in `seq`, of each unit length from 1 to
+/// 6 with at least `min_copies[length - 1]` copies, in order of their start.
+pub fn find_strs(seq: &[u8], min_copies: &[usize; 6]) -> Vec<StrLocus> {
+    let mut loci = Vec::new();
+    for period in 1..=6 {
+        // `from` is where the run of bases matching the one a period
+        // before began
+        let mut from = period;
+        for i in period..=seq.len() {
+            let repeats = i < seq.len() && {
+                let base = ENCODE[seq[i] as usize];
+                base != 4 && base == ENCODE[seq[i - period] as usize]
+            };
+            if repeats {
+                continue;
+            }
+            let (start, end) = (from - period, i);
+            let unit = &seq[start..start + period];
+            if end - start >= min_copies[period - 1] * period && is_primitive(unit) {
+                loci.push(StrLocus {
+                    start,
+                    end,
+                    unit: String::from_utf8_lossy(unit).to_ascii_uppercase(),
+                });
+            }
+            from = i + 1;
+        }
+    }
+    loci.sort_by_key(|locus| (locus.start, locus.end));
+    loci
+}
+
+/// Set the proportion of each window in short tandem repeats. The windows
+/// and loci are both in order along the sequence.
+pub fn count_window_strs(entries: &mut [Entry], loci: &[StrLocus]) {
+    // loci of different units can overlap, so are merged first
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for locus in loci {
+        match merged.last_mut() {
+            Some(last) if locus.start <= last.1 => last.1 = last.1.max(locus.end),
+            _ => merged.push((locus.start, locus.end)),
+        }
+    }
+    for entry in entries {
+        let in_strs: usize = runs::overlaps(&merged, (entry.start, entry.end)).sum();
+        entry.str_coverage = in_strs as f32 / (entry.end - entry.start) as f32;
+    }
+}
+
+/// The short tandem repeats of each sequence, as a BED of
+/// `ID start end unit copies`.
+pub struct Strs<W: Write> {
+    bed: W,
+}
+
+impl<W: Write> Strs<W> {
+    pub fn new(bed: W) -> Self {
+        Self { bed }
+    }
+}
+
+impl<W: Write> WriteWindows for Strs<W> {
+    fn write_record(&mut self, record: &RecordWindows) -> Result<()> {
+        for locus in record.strs.as_deref().unwrap_or_default() {
+            writeln!(
+                self.bed,
+                "{}\t{}\t{}\t{}\t{:.1}",
+                record.id,
+                locus.start,
+                locus.end,
+                locus.unit,
+                locus.copies()
+            )?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.bed.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_homopolymers() {
+        assert_eq!(window_homopolymers(b"AAACGGtTNNNNAcccc", 3), (4, 2));
+        assert_eq!(window_homopolymers(b"ACGT", 2), (1, 0));
+        assert_eq!(window_homopolymers(b"NNN", 1), (0, 0));
+        assert_eq!(window_homopolymers(b"", 1), (0, 0));
+    }
+
+    #[test]
+    fn test_strs() {
+        let seq = b"GGCACACACACACACACATTAGGTTAGGTTAGGTTAGGTTCAAAAAAAAAAAAAG";
+        let loci = find_strs(seq, &DEFAULT_STR_MIN_COPIES);
+        let found: Vec<(usize, usize, &str)> = loci
+            .iter()
+            .map(|l| (l.start, l.end, l.unit.as_str()))
+            .collect();
+        // (CA)8, (TTAGG)4 and A13; the As aren't also (AA)6
+        assert_eq!(found, [(2, 18, "CA"), (18, 40, "TTAGG"), (41, 54, "A")]);
+        assert_eq!(loci[1].copies(), 4.4);
+
+        assert!(is_primitive(b"ACG"));
+        assert!(!is_primitive(b"ACAC"));
+        assert!(find_strs(b"ACGTNNNNNNNNNNNNNNNN", &[2; 6]).is_empty());
+    }
+}
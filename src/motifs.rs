@@ -0,0 +1,191 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+
+// each base as a bit: A=1 C=2 G=4 T=8
+const fn iupac(code: u8) -> u8 {
+    match code.to_ascii_uppercase() {
+        b'A' => 0b0001,
+        b'C' => 0b0010,
+        b'G' => 0b0100,
+        b'T' | b'U' => 0b1000,
+        b'R' => 0b0101,
+        b'Y' => 0b1010,
+        b'S' => 0b0110,
+        b'W' => 0b1001,
+        b'K' => 0b1100,
+        b'M' => 0b0011,
+        b'B' => 0b1110,
+        b'D' => 0b1101,
+        b'H' => 0b1011,
+        b'V' => 0b0111,
+        b'N' => 0b1111,
+        _ => 0,
+    }
+}
+
+// the bases a sequence byte can match: only A, C, G and T match anything,
+// so an N in the sequence matches no pattern
+const BASE_BITS: [u8; 256] = build_base_bits();
+const fn build_base_bits() -> [u8; 256] {
+    let mut lut = [0u8; 256];
+    let bases = *b"ACGTacgt";
+    let mut i = 0;
+    while i < bases.len() {
+        lut[bases[i] as usize] = iupac(bases[i]);
+        i += 1;
+    }
+    lut
+}
+
+/// A named motif, with IUPAC degeneracy codes, counted on both strands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Motif {
+    pub name: String,
+    // the bases each position of the motif can match, and the same for its
+    // reverse complement
+    forward: Vec<u8>,
+    reverse: Vec<u8>,
+}
+
+impl Motif {
+    pub fn new(name: &str, pattern: &str) -> Result<Self> {
+        ensure!(!pattern.is_empty(), "motif {name} has no pattern");
+        let forward = pattern
+            .bytes()
+            .map(|code| match iupac(code) {
+                0 => bail!(
+                    "motif {name} has {:?}, which isn't an IUPAC base code",
+                    code as char
+                ),
+                bits => Ok(bits),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // complementing swaps A with T and C with G, so reverses the bits
+        let reverse = forward
+            .iter()
+            .rev()
+            .map(|bits| bits.reverse_bits() >> 4)
+            .collect();
+        Ok(Self {
+            name: name.to_string(),
+            forward,
+            reverse,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.forward.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.forward.is_empty()
+    }
+
+    /// Where the motif starts in `seq`, on either strand, in order. A site
+    /// matching both strands (such as a palindrome) is counted once. Unless
+    /// `overlapping`, a match starting inside the previous one is skipped.
+    pub fn matches(&self, seq: &[u8], overlapping: bool) -> Vec<usize> {
+        let mut starts = Vec::new();
+        let mut next_free = 0;
+        let fits = |pattern: &[u8], site: &[u8]| {
+            pattern
+                .iter()
+                .zip(site)
+                .all(|(&p, &b)| p & BASE_BITS[b as usize] != 0)
+        };
+        for (i, site) in seq.windows(self.len()).enumerate() {
+            if (overlapping || i >= next_free)
+                && (fits(&self.forward, site) || fits(&self.reverse, site))
+            {
+                starts.push(i);
+                next_free = i + self.len();
+            }
+        }
+        starts
+    }
+}
+
+/// Read motifs from a file of a name and an IUPAC pattern per line,
+/// separated by whitespace. Blank lines and lines starting with `#` are
+/// skipped.
+pub fn read_motifs(path: &Path) -> Result<Vec<Motif>> {
+    let text = read_to_string(path)
+        .with_context(|| format!("could not read motifs from {}", path.display()))?;
+    let mut motifs: Vec<Motif> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [name, pattern] = fields[..] else {
+            bail!(
+                "{}, line {}: expected a name and a pattern",
+                path.display(),
+                i + 1
+            );
+        };
+        ensure!(
+            motifs.iter().all(|m| m.name != name),
+            "{}, line {}: there is already a motif called {name}",
+            path.display(),
+            i + 1
+        );
+        let motif = Motif::new(name, pattern)
+            .with_context(|| format!("{}, line {}", path.display(), i + 1))?;
+        motifs.push(motif);
+    }
+    ensure!(!motifs.is_empty(), "{} has no motifs in it", path.display());
+    Ok(motifs)
+}
+
+/// The number of matches of each motif lying wholly within `start..end`,
+/// given where each motif starts along the sequence.
+pub fn count_in_window(
+    motifs: &[Motif],
+    starts: &[Vec<usize>],
+    (start, end): (usize, usize),
+) -> Vec<u32> {
+    motifs
+        .iter()
+        .zip(starts)
+        .map(|(motif, starts)| {
+            let first = starts.partition_point(|&i| i < start);
+            let last = starts.partition_point(|&i| i + motif.len() <= end);
+            last.saturating_sub(first) as u32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        // DpnII, a palindrome, is counted once per site
+        let dpnii = Motif::new("DpnII", "GATC").unwrap();
+        assert_eq!(dpnii.matches(b"AAGATCAAgatcNGATC", false), [2, 8, 13]);
+
+        // HinfI, with a degenerate base
+        let hinfi = Motif::new("HinfI", "GANTC").unwrap();
+        assert_eq!(hinfi.matches(b"GAATCxGACTCxGANTC", false), [0, 6]);
+
+        // found on the reverse strand too
+        let motif = Motif::new("m", "AAC").unwrap();
+        assert_eq!(motif.matches(b"AACGTT", false), [0, 3]);
+
+        // overlapping matches only if asked for
+        let motif = Motif::new("m", "AA").unwrap();
+        assert_eq!(motif.matches(b"AAAA", false), [0, 2]);
+        assert_eq!(motif.matches(b"AAAA", true), [0, 1, 2]);
+
+        assert!(Motif::new("bad", "GAXTC").is_err());
+
+        let starts = vec![dpnii.matches(b"AAGATCAAgatcNGATC", false)];
+        assert_eq!(count_in_window(&[dpnii], &starts, (0, 12)), [2]);
+        assert_eq!(count_in_window(&[], &[], (0, 12)), Vec::<u32>::new());
+    }
+}
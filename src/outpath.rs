@@ -0,0 +1,202 @@
+use std::ffi::OsString;
+use std::fs::{create_dir_all, remove_file, File, OpenOptions};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Error, Result};
+
+/// The directory output files are written to, unless told otherwise.
+pub const DEFAULT_OUTDIR: &str = "fw_out";
+
+/// The prefix all output files share: `<outdir>/<output>_<suffix>`.
+///
+/// `output` may contain directories of its own, which are created as
+/// needed. If it has any and no output directory is given, it is used as
+/// the prefix as it is, and if it is an absolute path the output directory
+/// is ignored.
+#[derive(Debug, Clone)]
+pub struct OutputPrefix {
+    prefix: PathBuf,
+    // overwrite existing files rather than refusing to
+    force: bool,
+}
+
+impl OutputPrefix {
+    pub fn new(outdir: Option<&Path>, output: &Path, force: bool) -> Self {
+        let has_dir = output.parent().is_some_and(|d| !d.as_os_str().is_empty());
+        let prefix = match outdir {
+            Some(outdir) => outdir.join(output),
+            None if has_dir => output.to_path_buf(),
+            None => Path::new(DEFAULT_OUTDIR).join(output),
+        };
+        Self { prefix, force }
+    }
+
+    /// The path of the output file with the given suffix.
+    pub fn path(&self, suffix: &str) -> PathBuf {
+        let mut name = OsString::from(self.prefix.as_os_str());
+        name.push("_");
+        name.push(suffix);
+        PathBuf::from(name)
+    }
+
+    /// Check that none of the output files with the given suffixes already
+    /// exists, unless `force` is set.
+    pub fn check<S: AsRef<str>>(&self, suffixes: &[S]) -> Result<()> {
+        if self.force {
+            return Ok(());
+        }
+        if let Some(existing) = suffixes
+            .iter()
+            .map(|s| self.path(s.as_ref()))
+            .find(|p| p.exists())
+        {
+            bail!(
+                "{} already exists, use --force to overwrite it",
+                existing.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Create every output file with the given suffixes.
+    ///
+    /// Unless `force` is set, nothing is created if any of them already
+    /// exists, so a previous run's results are never partly overwritten.
+    pub fn create<S: AsRef<str>>(&self, suffixes: &[S]) -> Result<Vec<BufWriter<File>>> {
+        self.check(suffixes)?;
+        let paths: Vec<PathBuf> = suffixes.iter().map(|s| self.path(s.as_ref())).collect();
+
+        if let Some(dir) = self.prefix.parent().filter(|d| !d.as_os_str().is_empty()) {
+            create_dir_all(dir)
+                .with_context(|| format!("could not create output directory {}", dir.display()))?;
+        }
+
+        paths
+            .iter()
+            .map(|path| {
+                let file = match self.force {
+                    true => File::create(path),
+                    false => OpenOptions::new().write(true).create_new(true).open(path),
+                };
+                file.map(BufWriter::new)
+                    .with_context(|| format!("could not create {}", path.display()))
+            })
+            .collect()
+    }
+
+    /// Remove the output files with the given suffixes, after a run that
+    /// failed, so it can be run again without `force`. Files that were
+    /// never created are skipped.
+    pub fn remove<S: AsRef<str>>(&self, suffixes: &[S]) {
+        for suffix in suffixes {
+            let _ = remove_file(self.path(suffix.as_ref()));
+        }
+    }
+}
+
+/// The format of the window statistics and k-mer count files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Tab separated text, the default.
+    Tsv,
+    /// Typed, compressed columns, for dataframes.
+    Parquet,
+    /// The Arrow IPC file format (Feather v2).
+    Arrow,
+}
+
+impl Format {
+    pub const NAMES: [&'static str; 3] = ["tsv", "parquet", "arrow"];
+
+    /// The file extension, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Tsv => "tsv",
+            Format::Parquet => "parquet",
+            Format::Arrow => "arrow",
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tsv" => Ok(Format::Tsv),
+            "parquet" => Ok(Format::Parquet),
+            "arrow" => Ok(Format::Arrow),
+            _ => bail!("unknown output format: {s}"),
+        }
+    }
+}
+
+impl std::fmt::Display for OutputPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.prefix.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths() {
+        let prefix = OutputPrefix::new(None, Path::new("sample"), false);
+        assert_eq!(
+            prefix.path("freq_windows.tsv"),
+            PathBuf::from("fw_out/sample_freq_windows.tsv")
+        );
+
+        // a directory in the output name is kept under a given outdir
+        let outdir = Some(Path::new("results"));
+        let prefix = OutputPrefix::new(outdir, Path::new("run1/sample"), false);
+        assert_eq!(
+            prefix.path("entropy.bed"),
+            PathBuf::from("results/run1/sample_entropy.bed")
+        );
+
+        // and used as the prefix itself without one
+        let prefix = OutputPrefix::new(None, Path::new("results/sample"), false);
+        assert_eq!(
+            prefix.path("entropy.bed"),
+            PathBuf::from("results/sample_entropy.bed")
+        );
+
+        // an absolute output name is always used as is
+        let outdir = Some(Path::new("fw_out"));
+        let prefix = OutputPrefix::new(outdir, Path::new("/data/sample"), false);
+        assert_eq!(
+            prefix.path("entropy.bed"),
+            PathBuf::from("/data/sample_entropy.bed")
+        );
+    }
+
+    #[test]
+    fn test_no_clobber() {
+        let dir = std::env::temp_dir().join(format!("fw_outpath_{}", std::process::id()));
+        let prefix = OutputPrefix::new(Some(&dir), Path::new("nested/sample"), false);
+
+        // creates the directories on the way
+        prefix.create(&["a.tsv", "b.tsv"]).unwrap();
+        assert!(prefix.path("a.tsv").exists());
+
+        // refuses to overwrite, and doesn't create the other files either
+        let err = prefix.create(&["c.tsv", "b.tsv"]).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        assert!(!prefix.path("c.tsv").exists());
+
+        let forced = OutputPrefix::new(Some(&dir), Path::new("nested/sample"), true);
+        forced.create(&["c.tsv", "b.tsv"]).unwrap();
+        assert!(forced.path("c.tsv").exists());
+
+        // after a failed run, the outputs are gone and it can be run again
+        prefix.remove(&["c.tsv", "b.tsv", "d.tsv"]);
+        assert!(!prefix.path("b.tsv").exists());
+        prefix.create(&["c.tsv", "b.tsv"]).unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
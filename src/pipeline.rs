@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+use anyhow::Result;
+use rayon::Yield;
+
+// how long a rayon thread with no jobs to run waits for a result before
+// looking for jobs again
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+/// How much work may be read ahead of the writer.
+///
+/// Reading pauses while either limit is reached, so peak memory is bounded
+/// by roughly `bases` of sequence (plus the record that crosses the limit)
+/// and the results for `records` records, however big the genome is.
+#[derive(Debug, Clone, Copy)]
+pub struct InFlight {
+    pub records: usize,
+    pub bases: usize,
+}
+
+impl Default for InFlight {
+    fn default() -> Self {
+        Self {
+            // enough to keep every thread busy on a genome of small scaffolds
+            records: 4 * rayon::current_num_threads(),
+            // but no more than a handful of large chromosomes
+            bases: 500_000_000,
+        }
+    }
+}
+
+/// Process each item from `source` on the rayon pool and pass the results
+/// to `write` in source order, as soon as each result and all those before
+/// it are done.
+///
+/// Results that finish early wait in a reorder buffer. The reading and
+/// writing both happen on the calling thread, so `write` needn't be `Send`.
+/// The first error from reading, processing or writing is returned.
+///
+/// It can be called from inside a rayon pool, even one of a single thread,
+/// as the calling thread runs queued jobs while it waits.
+pub fn process_in_order<T, R>(
+    source: impl Iterator<Item = Result<T>>,
+    limit: InFlight,
+    size: impl Fn(&T) -> usize,
+    process: impl Fn(T) -> Result<R> + Sync,
+    mut write: impl FnMut(R) -> Result<()>,
+) -> Result<()>
+where
+    T: Send,
+    R: Send,
+{
+    let process = &process;
+    let (sender, receiver) = channel::<(usize, usize, Result<R>)>();
+
+    rayon::in_place_scope(|scope| {
+        let mut source = source.enumerate();
+        let mut reorder: BTreeMap<usize, (usize, Result<R>)> = BTreeMap::new();
+        let mut next_to_write = 0;
+        let mut in_flight = (0, 0);
+        let mut exhausted = false;
+
+        // write out everything that's ready, in order
+        let mut flush = |reorder: &mut BTreeMap<_, _>, in_flight: &mut (usize, usize)| {
+            while let Some((bases, result)) = reorder.remove(&next_to_write) {
+                write(result?)?;
+                next_to_write += 1;
+                in_flight.0 -= 1;
+                in_flight.1 -= bases;
+            }
+            Ok::<(), anyhow::Error>(())
+        };
+
+        loop {
+            let full = in_flight.0 >= limit.records.max(1) || in_flight.1 >= limit.bases;
+            if exhausted || full {
+                if in_flight.0 == 0 {
+                    break;
+                }
+                // wait until something finishes. on a rayon thread, that
+                // means running queued jobs meanwhile, as they may be ours
+                // with no other thread free to run them
+                let (index, bases, result) = loop {
+                    if let Ok(message) = receiver.try_recv() {
+                        break message;
+                    }
+                    match rayon::yield_now() {
+                        Some(Yield::Executed) => {}
+                        // every job is already running somewhere
+                        Some(Yield::Idle) => {
+                            if let Ok(message) = receiver.recv_timeout(IDLE_WAIT) {
+                                break message;
+                            }
+                        }
+                        None => {
+                            break receiver
+                                .recv()
+                                .expect("a worker is always holding a sender")
+                        }
+                    }
+                };
+                reorder.insert(index, (bases, result));
+            } else {
+                match source.next() {
+                    Some((index, item)) => {
+                        let item = item?;
+                        let bases = size(&item);
+                        in_flight.0 += 1;
+                        in_flight.1 += bases;
+
+                        let sender = sender.clone();
+                        scope.spawn(move |_| {
+                            // the receiver is only dropped on error, when
+                            // nobody is waiting for this result anyway
+                            let _ = sender.send((index, bases, process(item)));
+                        });
+                    }
+                    None => exhausted = true,
+                }
+            }
+            // pick up anything else that's already finished
+            while let Ok((index, bases, result)) = receiver.try_recv() {
+                reorder.insert(index, (bases, result));
+            }
+            flush(&mut reorder, &mut in_flight)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results_in_source_order() {
+        let source = (0..200u64).map(Ok);
+        let mut written = Vec::new();
+        process_in_order(
+            source,
+            InFlight {
+                records: 8,
+                bases: usize::MAX,
+            },
+            |_| 1,
+            |i| {
+                // make the early items slow so they finish out of order
+                if i % 7 == 0 {
+                    std::thread::sleep(std::time::Duration::from_millis(2));
+                }
+                Ok(i * 2)
+            },
+            |r| {
+                written.push(r);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(written, (0..200u64).map(|i| i * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_inside_a_rayon_pool() {
+        use rayon::prelude::*;
+        let run = || {
+            let mut written = Vec::new();
+            process_in_order(
+                (0..50u64).map(Ok),
+                InFlight::default(),
+                |_| 1,
+                Ok,
+                |r| {
+                    written.push(r);
+                    Ok(())
+                },
+            )
+            .map(|()| written)
+        };
+        // a single thread has to run the jobs it is waiting for
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        assert_eq!(pool.install(run).unwrap(), (0..50).collect::<Vec<_>>());
+        // and every thread may be waiting at once
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let all: Vec<Vec<u64>> =
+            pool.install(|| (0..4).into_par_iter().map(|_| run().unwrap()).collect());
+        assert!(all.iter().all(|written| written.len() == 50));
+    }
+
+    #[test]
+    fn test_in_flight_is_bounded() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let started = AtomicUsize::new(0);
+        let mut written = 0;
+        let mut max_ahead = 0;
+        process_in_order(
+            (0..100usize).map(Ok),
+            InFlight {
+                records: 100,
+                bases: 30,
+            },
+            |_| 10,
+            |i| {
+                started.fetch_add(1, Ordering::SeqCst);
+                Ok(i)
+            },
+            |_| {
+                written += 1;
+                max_ahead = max_ahead.max(started.load(Ordering::SeqCst) - written);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(written, 100);
+        // never more than three records of ten bases read ahead
+        assert!(max_ahead < 3, "read {max_ahead} records ahead");
+    }
+
+    #[test]
+    fn test_errors_are_returned() {
+        let source = (0..50usize).map(|i| match i {
+            40 => Err(anyhow::anyhow!("bad record")),
+            i => Ok(i),
+        });
+        let result = process_in_order(source, InFlight::default(), |_| 1, Ok, |_| Ok(()));
+        assert_eq!(result.unwrap_err().to_string(), "bad record");
+
+        let result = process_in_order(
+            (0..50usize).map(Ok),
+            InFlight::default(),
+            |_| 1,
+            |i| match i {
+                10 => Err(anyhow::anyhow!("bad window")),
+                i => Ok(i),
+            },
+            |_| Ok(()),
+        );
+        assert_eq!(result.unwrap_err().to_string(), "bad window");
+    }
+}
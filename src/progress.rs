@@ -0,0 +1,62 @@
+use anyhow::Result;
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::input::Input;
+use crate::reader::BytesRead;
+
+/// A progress bar on stderr, measured in bases if the index gives the
+/// total, in bytes of the input file if not, and a spinner for stdin.
+pub struct Progress {
+    bar: ProgressBar,
+    // follow the reader rather than count bases
+    bytes_read: Option<BytesRead>,
+    // sequences done, and how many there are if known
+    done: usize,
+    expected: Option<usize>,
+}
+
+impl Progress {
+    pub fn new(input: &Input) -> Result<Self> {
+        let (bar, template, bytes_read) = match (input.expected, input.file_size) {
+            (Some((_, bases)), _) => (
+                ProgressBar::new(bases),
+                "[+]\tProcessed: {bar:40.cyan/blue} {human_pos}/{human_len} bases, {msg} ({eta})",
+                None,
+            ),
+            (None, Some(size)) => (
+                ProgressBar::new(size),
+                "[+]\tProcessed: {bar:40.cyan/blue} {bytes}/{total_bytes}, {msg} ({eta})",
+                Some(input.bytes_read.clone()),
+            ),
+            (None, None) => (
+                ProgressBar::new_spinner(),
+                "[+]\tProcessed: {spinner:.cyan} {bytes}, {msg}",
+                Some(input.bytes_read.clone()),
+            ),
+        };
+        bar.set_style(ProgressStyle::with_template(template)?.progress_chars("=> "));
+        Ok(Self {
+            bar,
+            bytes_read,
+            done: 0,
+            expected: input.expected.map(|(sequences, _)| sequences),
+        })
+    }
+
+    /// Count a sequence (or region) of `bases` as done.
+    pub fn inc(&mut self, bases: usize) {
+        self.done += 1;
+        match &self.bytes_read {
+            Some(bytes_read) => self.bar.set_position(bytes_read.get()),
+            None => self.bar.inc(bases as u64),
+        }
+        self.bar.set_message(match self.expected {
+            Some(expected) => format!("{}/{expected} sequences", self.done),
+            None => format!("{} sequences", self.done),
+        });
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish();
+    }
+}
@@ -0,0 +1,227 @@
+use std::fs::File;
+use std::io::{stdin, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use needletail::parser::SequenceRecord;
+use needletail::{parse_fastx_reader, FastxReader};
+
+use crate::error::FastaError;
+use crate::fai::{FaiEntry, FaiWriter};
+
+/// A FASTA record with newlines stripped from the sequence.
+pub struct FastaRecord {
+    pub id: String,
+    pub desc: Option<String>,
+    pub seq: Vec<u8>,
+    // where `seq` starts in the full sequence, if only a region was read
+    pub offset: usize,
+    // the length of the full sequence
+    pub full_len: usize,
+}
+
+/// Open a FASTA file, or stdin if the path is `-`.
+///
+/// The format is detected from the first bytes of the stream rather than
+/// the file extension, so plain, gzip, bgzip (multi-member gzip), bzip2, xz
+/// and zstd compressed input are all read transparently.
+pub fn open_fasta(path: &Path) -> Result<FastaRecords> {
+    if path == Path::new("-") {
+        return read_fasta(stdin(), "stdin");
+    }
+    let input = path.display().to_string();
+    let file = File::open(path).map_err(|source| FastaError::Io {
+        input: input.clone(),
+        source,
+    })?;
+    read_fasta(file, &input)
+}
+
+/// Read FASTA from any source, compressed or not, as for [`open_fasta`].
+/// `input` names it in errors.
+pub fn read_fasta(source: impl Read + Send + 'static, input: &str) -> Result<FastaRecords> {
+    let bytes_read = BytesRead::default();
+    let reader = parse_fastx_reader(Counted::new(source, &bytes_read))
+        .map_err(|e| FastaError::from_parse(input, e))?;
+    Ok(FastaRecords {
+        reader,
+        input: input.to_string(),
+        records: 0,
+        bytes_read,
+        index: None,
+    })
+}
+
+/// Whether a file is uncompressed fasta, which is all an index can be
+/// used (or built) for.
+pub fn is_plain_fasta(path: &Path) -> Result<bool> {
+    let mut first = [0; 1];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut first))
+        .with_context(|| format!("could not read {}", path.display()))?;
+    Ok(first[0] == b'>')
+}
+
+/// How many bytes of the (possibly compressed) input have been read so far.
+#[derive(Debug, Clone, Default)]
+pub struct BytesRead(Arc<AtomicU64>);
+
+impl BytesRead {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// counts the bytes passing through a reader
+struct Counted<R> {
+    inner: R,
+    count: BytesRead,
+}
+
+impl<R> Counted<R> {
+    fn new(inner: R, count: &BytesRead) -> Self {
+        Self {
+            inner,
+            count: count.clone(),
+        }
+    }
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.0.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// An iterator over the records of a FASTA file, in file order.
+pub struct FastaRecords {
+    reader: Box<dyn FastxReader>,
+    // what is being read, for errors
+    input: String,
+    // how many records have been read
+    records: usize,
+    bytes_read: BytesRead,
+    // writes the .fai index of the records as they are read
+    index: Option<FaiWriter>,
+}
+
+impl FastaRecords {
+    /// Write a `.fai` index of the file as it is read. The index is
+    /// complete once the last record has been read.
+    pub fn write_index(mut self, index: FaiWriter) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    pub fn bytes_read(&self) -> BytesRead {
+        self.bytes_read.clone()
+    }
+}
+
+impl Iterator for FastaRecords {
+    type Item = Result<FastaRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.reader.next() {
+            Some(Ok(record)) => record,
+            Some(Err(e)) => return Some(Err(FastaError::from_parse(&self.input, e).into())),
+            None if self.records == 0 => {
+                let input = self.input.clone();
+                return Some(Err(FastaError::EmptyInput { input }.into()));
+            }
+            None => {
+                return self
+                    .index
+                    .take()
+                    .and_then(|index| index.finish().err())
+                    .map(Err)
+            }
+        };
+        self.records += 1;
+        let (id, desc) = split_header(record.id());
+        if let Some(index) = &mut self.index {
+            let entry = FaiEntry::from_lines(id.clone(), seq_offset(&record), record.raw_seq());
+            if let Err(e) = entry.and_then(|entry| index.write(&entry)) {
+                return Some(Err(e));
+            }
+        }
+        // needletail's seq() strips newlines with SIMD memchr2 and returns Cow::Owned
+        // for multi-line sequences (the common case), so into_owned() is a move not a copy.
+        let seq = record.seq().into_owned();
+        Some(Ok(FastaRecord {
+            id,
+            desc,
+            full_len: seq.len(),
+            seq,
+            offset: 0,
+        }))
+    }
+}
+
+// the byte offset of the first base of a record
+fn seq_offset(record: &SequenceRecord) -> u64 {
+    let all = record.all();
+    let raw_seq = record.raw_seq();
+    let header = match raw_seq.is_empty() {
+        // the sequence would start on the line after the header
+        true => all
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(all.len(), |i| i + 1),
+        false => raw_seq.as_ptr() as usize - all.as_ptr() as usize,
+    };
+    record.position().byte() + header as u64
+}
+
+// the ID is everything up to the first whitespace,
+// and the description is what's left (if anything).
+pub(crate) fn split_header(header: &[u8]) -> (String, Option<String>) {
+    let header = String::from_utf8_lossy(header);
+    let mut fields = header.trim_end().splitn(2, char::is_whitespace);
+    let id = fields.next().unwrap_or_default().to_string();
+    let desc = fields
+        .next()
+        .map(|d| d.trim_start().to_string())
+        .filter(|d| !d.is_empty());
+    (id, desc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_header() {
+        assert_eq!(split_header(b"chr1"), ("chr1".to_string(), None));
+        assert_eq!(
+            split_header(b"OV656674.1 Homo sapiens\tchromosome 1"),
+            (
+                "OV656674.1".to_string(),
+                Some("Homo sapiens\tchromosome 1".to_string())
+            )
+        );
+        assert_eq!(split_header(b"chr1\t "), ("chr1".to_string(), None));
+    }
+
+    #[test]
+    fn test_bad_input_errors() {
+        let error = |fasta: &'static [u8]| -> FastaError {
+            let records = read_fasta(fasta, "test.fa").and_then(|records| {
+                records.collect::<Result<Vec<_>>>()?;
+                Ok(())
+            });
+            records.unwrap_err().downcast().unwrap()
+        };
+        assert!(matches!(error(b""), FastaError::EmptyInput { .. }));
+        assert!(matches!(error(b"\n"), FastaError::EmptyInput { .. }));
+        match error(b">one\nACGT\n>two") {
+            FastaError::Parse { line, .. } => assert_eq!(line, 3),
+            e => panic!("expected a parse error, not {e}"),
+        }
+        assert!(matches!(error(b"ACGT\n"), FastaError::Parse { .. }));
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use itertools::Itertools;
+
+use crate::fai::Fai;
+use crate::reader::{split_header, FastaRecord, FastaRecords};
+
+/// Part of a sequence to analyse, 0-based and half-open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub start: usize,
+    // to the end of the sequence if not given
+    pub end: Option<usize>,
+}
+
+impl Region {
+    // the coordinates of the region on a sequence of length `len`
+    pub(crate) fn bounds(&self, len: usize) -> Result<(usize, usize)> {
+        ensure!(
+            self.start <= len,
+            "region {self} starts past the end of {} ({len} bp)",
+            self.name
+        );
+        Ok((self.start, self.end.unwrap_or(len).min(len)))
+    }
+}
+
+/// A samtools-style region: `chr`, `chr:start` or `chr:start-end`, with
+/// 1-based inclusive coordinates and optional thousands separators.
+impl FromStr for Region {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let Some((name, range)) = s.rsplit_once(':') else {
+            ensure!(!s.is_empty(), "empty region");
+            return Ok(Region {
+                name: s.to_string(),
+                start: 0,
+                end: None,
+            });
+        };
+        ensure!(!name.is_empty(), "region {s:?} has no sequence name");
+
+        let number = |n: &str| {
+            n.trim()
+                .replace(',', "")
+                .parse::<usize>()
+                .with_context(|| format!("bad coordinate {n:?} in region {s:?}"))
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (number(start)?, Some(number(end)?)),
+            None => (number(range)?, None),
+        };
+        ensure!(start >= 1, "region {s:?} starts at 0, but is 1-based");
+        if let Some(end) = end {
+            ensure!(end >= start, "region {s:?} ends before it starts");
+        }
+        Ok(Region {
+            name: name.to_string(),
+            start: start - 1,
+            end,
+        })
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.start, self.end) {
+            (0, None) => write!(f, "{}", self.name),
+            (start, None) => write!(f, "{}:{}", self.name, start + 1),
+            (start, Some(end)) => write!(f, "{}:{}-{}", self.name, start + 1, end),
+        }
+    }
+}
+
+/// Read the intervals of a BED file (the first three columns only).
+pub fn read_bed(path: &Path) -> Result<Vec<Region>> {
+    let file = File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+    parse_bed(BufReader::new(file)).with_context(|| format!("could not read {}", path.display()))
+}
+
+fn parse_bed(reader: impl BufRead) -> Result<Vec<Region>> {
+    let mut regions = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let (Some(name), Some(start), Some(end)) = (fields.next(), fields.next(), fields.next())
+        else {
+            bail!("line {}: expected at least 3 tab separated columns", i + 1);
+        };
+        let number = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .with_context(|| format!("line {}: {n:?} is not a coordinate", i + 1))
+        };
+        let (start, end) = (number(start)?, number(end)?);
+        ensure!(start <= end, "line {}: the end is before the start", i + 1);
+        regions.push(Region {
+            name: name.to_string(),
+            start,
+            end: Some(end),
+        });
+    }
+    Ok(regions)
+}
+
+/// The regions given on the command line with `--region` and `--regions-bed`.
+pub fn from_args(matches: &clap::ArgMatches) -> Result<Vec<Region>> {
+    let mut regions = matches
+        .get_many::<String>("region")
+        .unwrap_or_default()
+        .map(|r| r.parse())
+        .collect::<Result<Vec<Region>>>()?;
+    if let Some(bed) = matches.get_one::<PathBuf>("regions_bed") {
+        regions.extend(read_bed(bed)?);
+    }
+    Ok(regions)
+}
+
+/// Check that the regions of each sequence come in order without
+/// overlapping, as the intervals of a bigWig have to.
+pub fn check_in_order(regions: &[Region]) -> Result<()> {
+    let mut last: HashMap<&str, &Region> = HashMap::new();
+    for region in regions {
+        if let Some(before) = last.insert(&region.name, region) {
+            ensure!(
+                before.end.is_some_and(|end| end <= region.start),
+                "regions {before} and {region} overlap or are out of order, which a bigWig \
+                 can't hold: give the regions of each sequence in order without overlaps, \
+                 or only write bedGraph tracks"
+            );
+        }
+    }
+    Ok(())
+}
+
+// regions read from an indexed fasta, seeking to each in turn
+pub(crate) struct IndexedRegions {
+    fasta: BufReader<File>,
+    fai: Fai,
+    regions: std::vec::IntoIter<(usize, Region)>,
+}
+
+impl IndexedRegions {
+    pub(crate) fn new(path: &Path, fai: Fai, regions: &[Region]) -> Result<Self> {
+        let mut located = regions
+            .iter()
+            .map(|region| match fai.get(&region.name) {
+                Some((index, _)) => Ok((index, region.clone())),
+                None => Err(anyhow!(
+                    "no sequence named {} in {}",
+                    region.name,
+                    path.display()
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // stable, so regions on one sequence stay in the order given
+        located.sort_by_key(|(index, _)| *index);
+
+        let file =
+            File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+        Ok(Self {
+            fasta: BufReader::new(file),
+            fai,
+            regions: located.into_iter(),
+        })
+    }
+
+    fn fetch(&mut self, index: usize, region: &Region) -> Result<FastaRecord> {
+        let entry = &self.fai.entries()[index];
+        let (start, end) = region.bounds(entry.len)?;
+        let (id, desc) = split_header(&self.fai.header(&mut self.fasta, index)?);
+        Ok(FastaRecord {
+            id,
+            desc,
+            seq: entry.fetch(&mut self.fasta, start, end)?,
+            offset: start,
+            full_len: entry.len,
+        })
+    }
+}
+
+impl Iterator for IndexedRegions {
+    type Item = Result<FastaRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (index, region) = self.regions.next()?;
+        Some(self.fetch(index, &region))
+    }
+}
+
+// regions cut from the records as they stream past, stopping as soon as
+// every region has been found
+pub(crate) struct StreamedRegions {
+    records: FastaRecords,
+    wanted: HashMap<String, Vec<Region>>,
+    ready: VecDeque<FastaRecord>,
+}
+
+impl StreamedRegions {
+    pub(crate) fn new(records: FastaRecords, regions: &[Region]) -> Self {
+        let mut wanted: HashMap<String, Vec<Region>> = HashMap::new();
+        for region in regions {
+            wanted
+                .entry(region.name.clone())
+                .or_default()
+                .push(region.clone());
+        }
+        Self {
+            records,
+            wanted,
+            ready: VecDeque::new(),
+        }
+    }
+
+    fn cut(&mut self, record: FastaRecord, regions: Vec<Region>) -> Result<()> {
+        for region in regions {
+            let (start, end) = region.bounds(record.seq.len())?;
+            self.ready.push_back(FastaRecord {
+                id: record.id.clone(),
+                desc: record.desc.clone(),
+                seq: record.seq[start..end].to_vec(),
+                offset: start,
+                full_len: record.seq.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for StreamedRegions {
+    type Item = Result<FastaRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.ready.pop_front() {
+                return Some(Ok(record));
+            }
+            if self.wanted.is_empty() {
+                return None;
+            }
+            let record = match self.records.next() {
+                Some(Ok(record)) => record,
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    let missing = self
+                        .wanted
+                        .drain()
+                        .map(|(name, _)| name)
+                        .sorted()
+                        .join(", ");
+                    return Some(Err(anyhow!("no sequence named {missing} in the fasta")));
+                }
+            };
+            if let Some(regions) = self.wanted.remove(&record.id) {
+                if let Err(e) = self.cut(record, regions) {
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(name: &str, start: usize, end: Option<usize>) -> Region {
+        Region {
+            name: name.to_string(),
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!("chr1".parse::<Region>().unwrap(), region("chr1", 0, None));
+        assert_eq!(
+            "chr3:10,000,001-12,000,000".parse::<Region>().unwrap(),
+            region("chr3", 10_000_000, Some(12_000_000))
+        );
+        assert_eq!(
+            "HLA-A*01:01:1-100".parse::<Region>().unwrap(),
+            region("HLA-A*01:01", 0, Some(100))
+        );
+        assert_eq!("chrX:5".parse::<Region>().unwrap(), region("chrX", 4, None));
+        assert!("chr1:0-10".parse::<Region>().is_err());
+        assert!("chr1:10-5".parse::<Region>().is_err());
+        assert!("chr1:1k-2k".parse::<Region>().is_err());
+        assert!(":1-2".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn test_check_in_order() {
+        let regions = [
+            region("chr1", 0, Some(100)),
+            region("chr2", 0, None),
+            region("chr1", 100, Some(200)),
+        ];
+        assert!(check_in_order(&regions).is_ok());
+        assert!(check_in_order(&[region("chr1", 0, Some(100)), region("chr1", 50, None)]).is_err());
+        assert!(
+            check_in_order(&[region("chr1", 100, Some(200)), region("chr1", 0, Some(100))])
+                .is_err()
+        );
+        assert!(check_in_order(&[region("chr1", 100, None), region("chr1", 200, None)]).is_err());
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for s in ["chr1", "chr2:5", "chr3:1-100"] {
+            assert_eq!(s.parse::<Region>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_parse_bed() {
+        let bed = "track name=cen\n# comment\nchr1\t0\t100\tcen1\nchr2\t50\t60\n\n";
+        assert_eq!(
+            parse_bed(bed.as_bytes()).unwrap(),
+            vec![region("chr1", 0, Some(100)), region("chr2", 50, Some(60))]
+        );
+        assert!(parse_bed("chr1\t0\n".as_bytes()).is_err());
+        assert!(parse_bed("chr1\t100\t0\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_bounds() {
+        assert_eq!(region("a", 10, None).bounds(100).unwrap(), (10, 100));
+        assert_eq!(region("a", 10, Some(1000)).bounds(100).unwrap(), (10, 100));
+        assert!(region("a", 200, None).bounds(100).is_err());
+    }
+}
@@ -0,0 +1,49 @@
+/// The runs of bases in `seq` for which `in_run` holds, at least `min_len`
+/// long, as half-open ranges.
+pub fn find_runs(seq: &[u8], min_len: usize, in_run: impl Fn(u8) -> bool) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut start = None;
+    for (i, &b) in seq.iter().enumerate() {
+        match (in_run(b), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                if i - s >= min_len {
+                    runs.push((s, i));
+                }
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    if let Some(s) = start.filter(|&s| seq.len() - s >= min_len) {
+        runs.push((s, seq.len()));
+    }
+    runs
+}
+
+/// How much of each run touching `start..end` is inside it. The runs are
+/// in order along the sequence.
+pub fn overlaps(
+    runs: &[(usize, usize)],
+    (start, end): (usize, usize),
+) -> impl Iterator<Item = usize> + '_ {
+    let first = runs.partition_point(|&(_, run_end)| run_end <= start);
+    runs[first..]
+        .iter()
+        .take_while(move |&&(run_start, _)| run_start < end)
+        .map(move |&(run_start, run_end)| run_end.min(end) - run_start.max(start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs() {
+        let runs = find_runs(b"aaCGTacGTTt", 1, |b| b.is_ascii_lowercase());
+        assert_eq!(runs, [(0, 2), (5, 7), (10, 11)]);
+        assert_eq!(find_runs(b"aaCGTacGTTt", 2, |b| b == b'a'), [(0, 2)]);
+        assert_eq!(overlaps(&runs, (1, 6)).collect::<Vec<_>>(), [1, 1]);
+        assert_eq!(overlaps(&runs, (2, 5)).count(), 0);
+    }
+}
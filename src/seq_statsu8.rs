@@ -1,11 +1,9 @@
-use std::collections::HashMap;
-
 pub struct SeqStats {
     pub gc_proportion: f32,
     pub gc_skew: f32,
     pub at_skew: f32,
     pub shannon_entropy: f64,
-    pub nuc_counts: Vec<i32>,
+    pub nuc_counts: Vec<i64>,
     // proportions of nucleotides (& N's)
     pub g_s: f32,
     pub c_s: f32,
@@ -18,90 +16,138 @@ pub struct SeqStats {
 // function below reveals other ambiguous bases present in assemblies, not sure
 // how to deal with those yet.
 // count the number of each nucleotide in a given sequence window.
-// I've learned that bio::fasta does UTF8 checks, so we can be confident
-// of all the characters in the sequence
-fn nucleotide_counts(dna: &[u8]) -> HashMap<&u8, i32> {
-    let mut map = HashMap::new();
+fn nucleotide_counts(dna: &[u8]) -> ByteCounts {
+    let mut counts = ByteCounts::new();
     for nucleotide in dna {
-        let count = map.entry(nucleotide).or_insert(0);
-        *count += 1;
+        counts.add(*nucleotide);
+    }
+    counts
+}
+
+// a count of every byte in a window, which can be updated
+// one base at a time as a window slides along a sequence.
+#[derive(Clone)]
+pub struct ByteCounts {
+    counts: [i64; 256],
+    len: usize,
+}
+
+impl Default for ByteCounts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ByteCounts {
+    pub fn new() -> Self {
+        Self {
+            counts: [0; 256],
+            len: 0,
+        }
+    }
+
+    pub fn add(&mut self, byte: u8) {
+        self.counts[byte as usize] += 1;
+        self.len += 1;
+    }
+
+    pub fn remove(&mut self, byte: u8) {
+        self.counts[byte as usize] -= 1;
+        self.len -= 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.counts = [0; 256];
+        self.len = 0;
+    }
+
+    /// Add the counts of another stretch of sequence.
+    pub fn merge(&mut self, other: &ByteCounts) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.len += other.len;
+    }
+
+    pub fn get(&self, byte: u8) -> i64 {
+        self.counts[byte as usize]
+    }
+
+    /// The counts of A, C, G and T in either case, as the dinucleotides
+    /// are counted even with `--masked`.
+    pub fn acgt(&self) -> [i64; 4] {
+        b"ACGT".map(|base| self.get(base) + self.get(base.to_ascii_lowercase()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
-    map
 }
 
 // store each u8 in a hashmap, extract the values and summarise
 // add in here option to pass over lowercase letters
 pub fn seq_stats(dna: &[u8], masked: bool) -> SeqStats {
+    seq_stats_from_counts(&nucleotide_counts(dna), masked)
+}
+
+// the same statistics as above, from counts that have already been made,
+// e.g. by adding and removing bases as a window slides.
+pub fn seq_stats_from_counts(counts: &ByteCounts, masked: bool) -> SeqStats {
     // sequence length
-    let length: f32 = dna.len() as f32;
-    // G/C/A/T counts
-    let counts = nucleotide_counts(dna);
+    let length: f32 = counts.len() as f32;
     // upper and lower cases accounted for.
 
-    let g_counts: i32;
-    let c_counts: i32;
-    let a_counts: i32;
-    let t_counts: i32;
-    let n_counts: i32;
-    let masked_counts: i32;
-    let w_counts: i32;
-    let s_counts: i32;
+    let g_counts: i64;
+    let c_counts: i64;
+    let a_counts: i64;
+    let t_counts: i64;
+    let n_counts: i64;
+    let masked_counts: i64;
+    let w_counts: i64;
+    let s_counts: i64;
 
     if masked {
-        g_counts = *counts.get(&b'G').unwrap_or(&0);
-        c_counts = *counts.get(&b'C').unwrap_or(&0);
-        a_counts = *counts.get(&b'A').unwrap_or(&0);
-        t_counts = *counts.get(&b'T').unwrap_or(&0);
-        n_counts = *counts.get(&b'N').unwrap_or(&0);
+        g_counts = counts.get(b'G');
+        c_counts = counts.get(b'C');
+        a_counts = counts.get(b'A');
+        t_counts = counts.get(b'T');
+        n_counts = counts.get(b'N');
         masked_counts = 0;
-        w_counts = *counts.get(&b'W').unwrap_or(&0);
-        s_counts = *counts.get(&b'S').unwrap_or(&0);
+        w_counts = counts.get(b'W');
+        s_counts = counts.get(b'S');
     } else {
-        g_counts = counts.get(&b'G').unwrap_or(&0) + counts.get(&b'g').unwrap_or(&0);
-        c_counts = counts.get(&b'C').unwrap_or(&0) + counts.get(&b'c').unwrap_or(&0);
-        a_counts = counts.get(&b'A').unwrap_or(&0) + counts.get(&b'a').unwrap_or(&0);
-        t_counts = counts.get(&b'T').unwrap_or(&0) + counts.get(&b't').unwrap_or(&0);
-        n_counts = counts.get(&b'N').unwrap_or(&0) + counts.get(&b'n').unwrap_or(&0);
+        g_counts = counts.get(b'G') + counts.get(b'g');
+        c_counts = counts.get(b'C') + counts.get(b'c');
+        a_counts = counts.get(b'A') + counts.get(b'a');
+        t_counts = counts.get(b'T') + counts.get(b't');
+        n_counts = counts.get(b'N') + counts.get(b'n');
         // All valid lower case bases
-        masked_counts = counts.get(&b'a').unwrap_or(&0)
-            + counts.get(&b'c').unwrap_or(&0)
-            + counts.get(&b'g').unwrap_or(&0)
-            + counts.get(&b't').unwrap_or(&0)
-            + counts.get(&b'm').unwrap_or(&0)
-            + counts.get(&b'r').unwrap_or(&0)
-            + counts.get(&b'w').unwrap_or(&0)
-            + counts.get(&b's').unwrap_or(&0)
-            + counts.get(&b'y').unwrap_or(&0)
-            + counts.get(&b'k').unwrap_or(&0)
-            + counts.get(&b'v').unwrap_or(&0)
-            + counts.get(&b'h').unwrap_or(&0)
-            + counts.get(&b'b').unwrap_or(&0)
-            + counts.get(&b'd').unwrap_or(&0)
-            + counts.get(&b'n').unwrap_or(&0);
+        masked_counts = b"acgtmrwsykvhbdn".iter().map(|&b| counts.get(b)).sum();
         // additional weak bases (ambiguous A/T)
-        w_counts = counts.get(&b'W').unwrap_or(&0) + counts.get(&b'w').unwrap_or(&0);
+        w_counts = counts.get(b'W') + counts.get(b'w');
         // additional strong bases (ambiguous G/C)
-        s_counts = counts.get(&b'S').unwrap_or(&0) + counts.get(&b's').unwrap_or(&0);
+        s_counts = counts.get(b'S') + counts.get(b's');
     }
 
     // shannon entropy of the window
     // see https://github.com/fkie-cad/entropython/blob/main/src/lib.rs
 
     let mut byte_count = [0u64; 256];
-    for byte in dna {
+    for (byte, &count) in counts.counts.iter().enumerate() {
         // change lowercase nucleotides to uppercase ones.
-        match byte {
-            b'g' => byte_count[b'G' as usize] += 1,
-            b'c' => byte_count[b'C' as usize] += 1,
-            b'a' => byte_count[b'A' as usize] += 1,
-            b't' => byte_count[b'T' as usize] += 1,
-            b'n' => byte_count[b'N' as usize] += 1,
-            _ => byte_count[*byte as usize] += 1,
-        }
+        let byte = match byte as u8 {
+            b'g' | b'c' | b'a' | b't' | b'n' => (byte as u8).to_ascii_uppercase(),
+            b => b,
+        };
+        byte_count[byte as usize] += count as u64;
     }
     let mut entropy = 0f64;
     for counted_num in byte_count.iter().filter(|num| **num > 0u64) {
-        let byte_probability = *counted_num as f64 / (dna.len() as f64);
+        let byte_probability = *counted_num as f64 / (counts.len() as f64);
         entropy -= byte_probability * byte_probability.log2();
     }
     SeqStats {
@@ -121,12 +167,57 @@ pub fn seq_stats(dna: &[u8], masked: bool) -> SeqStats {
     }
 }
 
+/// The CpG observed/expected ratio of Gardiner-Garden & Frommer (1987),
+/// CpG * length / (C * G), over the A, C, G and T bases. It is 0 if there
+/// are no Cs or no Gs, as no CpGs are expected.
+pub fn cpg_observed_expected(nuc_counts: &[i64], cpg: u64) -> f32 {
+    let [a, c, g, t] = [0, 1, 2, 3].map(|i| nuc_counts[i] as f64);
+    match c * g > 0.0 {
+        true => (cpg as f64 * (a + c + g + t) / (c * g)) as f32,
+        false => 0.0,
+    }
+}
+
+/// Karlin's dinucleotide relative abundances, rho(XY) = f(XY) / (f(X) f(Y)),
+/// from the A, C, G, T counts and the 16 dinucleotide counts by code.
+///
+/// Symmetrised, these are rho*(XY) over the sequence and its reverse
+/// complement together, for the 10 canonical dinucleotides in code order.
+/// As for the CpG o/e, a dinucleotide whose bases aren't there at all
+/// (or a window without any dinucleotides) is 0.
+pub fn karlin_rho(nuc_counts: &[i64], dinucs: &[u32; 16], symmetrised: bool) -> Vec<f32> {
+    let mut mono = [0, 1, 2, 3].map(|i| nuc_counts[i] as f64);
+    let mut di = dinucs.map(|count| count as f64);
+    if symmetrised {
+        // add the counts on the other strand: complement X is 3 - X, and the
+        // reverse complement of XY is (3 - Y)(3 - X)
+        mono = std::array::from_fn(|x| mono[x] + mono[3 - x]);
+        di = std::array::from_fn(|code| {
+            let (x, y) = (code >> 2, code & 3);
+            di[code] + di[(3 - y) << 2 | (3 - x)]
+        });
+    }
+    let mono_total: f64 = mono.iter().sum();
+    let di_total: f64 = di.iter().sum();
+    (0..16)
+        .filter(|&code| !symmetrised || code <= crate::kmer_maps::reverse_complement(code, 2))
+        .map(|code| {
+            let (x, y) = (code >> 2, code & 3);
+            let expected = (mono[x] / mono_total) * (mono[y] / mono_total);
+            match expected > 0.0 && di_total > 0.0 {
+                true => ((di[code] / di_total) / expected) as f32,
+                false => 0.0,
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
 
     use crate::seq_statsu8::seq_stats;
 
-    use super::nucleotide_counts;
+    use super::{cpg_observed_expected, karlin_rho, nucleotide_counts, seq_stats_from_counts};
 
     const A: u8 = b'A';
     const C: u8 = b'C';
@@ -140,10 +231,10 @@ mod tests {
         let nuc_counts = nucleotide_counts(short_dna_string);
 
         // Two of each!
-        assert_eq!(2, *nuc_counts.get(&A).unwrap());
-        assert_eq!(2, *nuc_counts.get(&C).unwrap());
-        assert_eq!(2, *nuc_counts.get(&G).unwrap());
-        assert_eq!(2, *nuc_counts.get(&T).unwrap());
+        assert_eq!(2, nuc_counts.get(A));
+        assert_eq!(2, nuc_counts.get(C));
+        assert_eq!(2, nuc_counts.get(G));
+        assert_eq!(2, nuc_counts.get(T));
     }
 
     #[test]
@@ -165,4 +256,72 @@ mod tests {
         // 4 out of 10 bases are GC
         assert_eq!(0.4, stats.gc_proportion);
     }
+
+    #[test]
+    fn test_sliding_counts_match_fresh() {
+        let dna = "ACGTNNacgtSWAAAC".as_bytes();
+        // slide a window of 8 along by one base at a time
+        let mut counts = nucleotide_counts(&dna[0..8]);
+        for start in 1..=dna.len() - 8 {
+            counts.remove(dna[start - 1]);
+            counts.add(dna[start + 7]);
+
+            let fresh = seq_stats(&dna[start..start + 8], false);
+            let slid = seq_stats_from_counts(&counts, false);
+            assert_eq!(fresh.nuc_counts, slid.nuc_counts);
+            assert_eq!(fresh.masked, slid.masked);
+            assert_eq!(fresh.shannon_entropy, slid.shannon_entropy);
+        }
+    }
+
+    #[test]
+    fn test_cpg_observed_expected() {
+        // 2 CpGs in 8 bases with 2 Cs and 2 Gs, when 0.5 would be expected
+        let stats = seq_stats(b"ACGTACGT", false);
+        assert_eq!(cpg_observed_expected(&stats.nuc_counts, 2), 4.0);
+
+        // no Cs, so no CpGs are expected
+        let stats = seq_stats(b"AAGGTTGG", false);
+        assert_eq!(cpg_observed_expected(&stats.nuc_counts, 0), 0.0);
+        assert_eq!(cpg_observed_expected(&[0; 5], 0), 0.0);
+
+        // the CpGs are counted in either case, so the bases are too
+        let counts = nucleotide_counts(b"aacgCGtT");
+        assert_eq!(counts.acgt(), [2, 2, 2, 2]);
+        assert_eq!(cpg_observed_expected(&counts.acgt(), 2), 4.0);
+    }
+
+    #[test]
+    fn test_karlin_rho() {
+        // ACGT repeated: AC, CG, GT and TA each 1/4 of the dinucleotides,
+        // where 1/16 would be expected
+        let stats = seq_stats(b"ACGTACGTACGTA", false);
+        let mut dinucs = [0; 16];
+        for code in [0b00_01, 0b01_10, 0b10_11] {
+            dinucs[code] = 3;
+        }
+        dinucs[0b11_00] = 3;
+        let rho = karlin_rho(&stats.nuc_counts, &dinucs, false);
+        assert_eq!(rho.len(), 16);
+        assert!(rho[0b00_01] > 3.0 && rho[0b00_01] < 5.0);
+        assert_eq!(rho[0b00_00], 0.0);
+
+        // symmetrised, AC and its reverse complement GT share a value
+        let rho = karlin_rho(&stats.nuc_counts, &dinucs, true);
+        assert_eq!(rho.len(), 10);
+        // in order AA AC AG AT CA CC CG GA GC TA
+        assert!(rho[1] > 3.0 && rho[1] < 5.0);
+        assert!(rho[6] > 3.0 && rho[6] < 5.0);
+        assert_eq!(rho[0], 0.0);
+
+        // nothing to divide by
+        assert!(karlin_rho(&[0; 5], &[0; 16], false)
+            .iter()
+            .all(|&r| r == 0.0));
+        let stats = seq_stats(b"AAAA", false);
+        let mut dinucs = [0; 16];
+        dinucs[0] = 3;
+        let rho = karlin_rho(&stats.nuc_counts, &dinucs, true);
+        assert!(rho.iter().all(|r| r.is_finite()));
+    }
 }
@@ -0,0 +1,168 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+
+/// How sequences are ordered in the output files.
+///
+/// By default output follows the order of the input FASTA, which is what
+/// `.fai` and AGP files assume. These are opt-in alternatives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Byte-wise ordering of IDs (chr1, chr10, chr2).
+    Lexicographic,
+    /// Runs of digits are compared numerically (chr1, chr2, chr10).
+    Natural,
+    /// Longest sequence first; ties keep their input order.
+    Length,
+}
+
+impl SortOrder {
+    pub const NAMES: [&'static str; 3] = ["lexicographic", "natural", "length"];
+
+    /// Compare two sequences given as (ID, length) pairs.
+    pub fn compare(&self, a: (&str, usize), b: (&str, usize)) -> Ordering {
+        match self {
+            SortOrder::Lexicographic => a.0.cmp(b.0),
+            SortOrder::Natural => natural_cmp(a.0, b.0),
+            SortOrder::Length => b.1.cmp(&a.1),
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lexicographic" => Ok(SortOrder::Lexicographic),
+            "natural" => Ok(SortOrder::Natural),
+            "length" => Ok(SortOrder::Length),
+            _ => bail!("unknown sort order: {s}"),
+        }
+    }
+}
+
+// version sort: split into digit and non-digit chunks, comparing
+// digit chunks by numeric value and everything else byte-wise.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+
+    while !a.is_empty() && !b.is_empty() {
+        let a_digit = a[0].is_ascii_digit();
+        let b_digit = b[0].is_ascii_digit();
+
+        let ord = match (a_digit, b_digit) {
+            (true, true) => {
+                let (a_num, a_rest) = split_chunk(a, true);
+                let (b_num, b_rest) = split_chunk(b, true);
+                a = a_rest;
+                b = b_rest;
+                cmp_numeric(a_num, b_num)
+            }
+            (false, false) => {
+                let (a_txt, a_rest) = split_chunk(a, false);
+                let (b_txt, b_rest) = split_chunk(b, false);
+                a = a_rest;
+                b = b_rest;
+                a_txt.cmp(b_txt)
+            }
+            // digits sort before letters, as in `sort -V`
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+        };
+
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+fn split_chunk(s: &[u8], digits: bool) -> (&[u8], &[u8]) {
+    let end = s
+        .iter()
+        .position(|c| c.is_ascii_digit() != digits)
+        .unwrap_or(s.len());
+    s.split_at(end)
+}
+
+// compare two digit strings by value without parsing,
+// so arbitrarily long numbers don't overflow.
+fn cmp_numeric(a: &[u8], b: &[u8]) -> Ordering {
+    let a_trim = &a[a.iter().position(|&c| c != b'0').unwrap_or(a.len())..];
+    let b_trim = &b[b.iter().position(|&c| c != b'0').unwrap_or(b.len())..];
+
+    a_trim
+        .len()
+        .cmp(&b_trim.len())
+        .then_with(|| a_trim.cmp(b_trim))
+        // on equal value, fewer leading zeros first
+        .then_with(|| a.len().cmp(&b.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(order: SortOrder, ids: &[(&'static str, usize)]) -> Vec<&'static str> {
+        let mut ids = ids.to_vec();
+        ids.sort_by(|a, b| order.compare(*a, *b));
+        ids.into_iter().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn test_lexicographic() {
+        let ids = [("chr2", 1), ("chr10", 1), ("chr1", 1)];
+        assert_eq!(
+            sorted(SortOrder::Lexicographic, &ids),
+            vec!["chr1", "chr10", "chr2"]
+        );
+    }
+
+    #[test]
+    fn test_natural() {
+        let ids = [
+            ("chr10", 1),
+            ("chr2", 1),
+            ("chrX", 1),
+            ("chr1", 1),
+            ("scaffold_100", 1),
+            ("scaffold_20", 1),
+        ];
+        assert_eq!(
+            sorted(SortOrder::Natural, &ids),
+            vec![
+                "chr1",
+                "chr2",
+                "chr10",
+                "chrX",
+                "scaffold_20",
+                "scaffold_100"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_natural_accessions() {
+        assert_eq!(natural_cmp("OV656674.1", "OV656674.2"), Ordering::Less);
+        assert_eq!(natural_cmp("OV656674.10", "OV656674.9"), Ordering::Greater);
+        assert_eq!(natural_cmp("chr01", "chr1"), Ordering::Greater);
+        assert_eq!(natural_cmp("chr1", "chr1"), Ordering::Equal);
+        assert_eq!(natural_cmp("chr1", "chr1a"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_length_is_stable() {
+        let ids = [("a", 10), ("b", 30), ("c", 10), ("d", 20)];
+        assert_eq!(sorted(SortOrder::Length, &ids), vec!["b", "d", "a", "c"]);
+    }
+
+    #[test]
+    fn test_from_str() {
+        for name in SortOrder::NAMES {
+            assert!(name.parse::<SortOrder>().is_ok());
+        }
+        assert!("alphabetical".parse::<SortOrder>().is_err());
+    }
+}
@@ -0,0 +1,420 @@
+use std::io::Write;
+
+use anyhow::Result;
+use rayon::prelude::*;
+
+use crate::fw::{RecordWindows, WriteWindows, CG};
+use crate::kmer_maps::{each_kmer, KmerMap};
+use crate::kmeru8::shannon_diversity;
+use crate::seq_statsu8::{self, ByteCounts};
+
+// how much sequence each parallel block of a summary counts
+const BLOCK_BASES: usize = 1 << 22;
+
+/// The counts over a whole sequence that its summary is made from, which
+/// add up over sequences to the summary of the genome.
+///
+/// Only the dense k-mer sizes are counted, as the distinct longer k-mers
+/// of a chromosome are too many to hold.
+#[derive(Clone)]
+pub struct SequenceTotals {
+    bytes: ByteCounts,
+    // for the CpGs, whichever k-mers are counted
+    dinucs: Vec<u64>,
+    // the count of every k-mer by code, for each dense size
+    kmers: Vec<(KmerMap, Vec<u64>)>,
+    // runs of Ns at least the minimum gap long
+    gaps: u64,
+}
+
+impl SequenceTotals {
+    fn empty(kmer_maps: &[KmerMap]) -> Self {
+        Self {
+            bytes: ByteCounts::new(),
+            dinucs: vec![0; 16],
+            kmers: kmer_maps
+                .iter()
+                .filter(|m| m.is_dense())
+                .map(|m| (*m, vec![0; m.size()]))
+                .collect(),
+            gaps: 0,
+        }
+    }
+
+    /// Count everything in `seq`, in parallel blocks, with runs of Ns at
+    /// least `min_gap` long as gaps.
+    pub fn new(seq: &[u8], kmer_maps: &[KmerMap], min_gap: usize) -> Self {
+        (0..seq.len().div_ceil(BLOCK_BASES))
+            .into_par_iter()
+            .map(|b| {
+                let block = b * BLOCK_BASES..((b + 1) * BLOCK_BASES).min(seq.len());
+                let mut totals = Self::empty(kmer_maps);
+                totals.count(seq, block, min_gap);
+                totals
+            })
+            .reduce(
+                || Self::empty(kmer_maps),
+                |mut a, b| {
+                    a.merge(&b);
+                    a
+                },
+            )
+    }
+
+    // count the bases in `block`, and the k-mers and gaps starting there
+    fn count(&mut self, seq: &[u8], block: std::ops::Range<usize>, min_gap: usize) {
+        for &b in &seq[block.clone()] {
+            self.bytes.add(b);
+        }
+        // k-mers that start in the block, even if they end after it
+        let starts = |k: usize| block.start..block.end.min((seq.len() + 1).saturating_sub(k));
+        each_kmer(seq, 2, starts(2), |code| self.dinucs[code] += 1);
+        for (kmer_map, counts) in &mut self.kmers {
+            let code_map = kmer_map.code_map();
+            each_kmer(seq, kmer_map.len, starts(kmer_map.len), |code| {
+                counts[code_map.get(code)] += 1
+            });
+        }
+
+        // a gap is counted in the block it starts in, however far it runs on,
+        // and is as long as it would be to `gaps::find_gaps`
+        let is_n = |i: usize| matches!(seq[i], b'N' | b'n');
+        let mut i = block.start;
+        while i < block.end {
+            if !is_n(i) || (i > 0 && is_n(i - 1)) {
+                i += 1;
+                continue;
+            }
+            let end = (i..seq.len()).find(|&j| !is_n(j)).unwrap_or(seq.len());
+            self.gaps += (end - i >= min_gap) as u64;
+            i = end;
+        }
+    }
+
+    fn merge(&mut self, other: &SequenceTotals) {
+        self.bytes.merge(&other.bytes);
+        add(&mut self.dinucs, &other.dinucs);
+        for ((_, counts), (_, other)) in self.kmers.iter_mut().zip(&other.kmers) {
+            add(counts, other);
+        }
+        self.gaps += other.gaps;
+    }
+}
+
+fn add(counts: &mut [u64], other: &[u64]) {
+    for (count, other) in counts.iter_mut().zip(other) {
+        *count += other;
+    }
+}
+
+// the statistics of one row of the summary
+struct Row {
+    id: String,
+    desc: String,
+    length: u64,
+    non_n_length: u64,
+    gc_proportion: f32,
+    gc_skew: f32,
+    at_skew: f32,
+    shannon_entropy: f64,
+    n_s: f32,
+    masked: f32,
+    cpg_s: f32,
+    cpg_oe: f32,
+    gaps: u64,
+    kmer_shannon: Vec<f64>,
+    // only for the genome
+    n50_n90: Option<(u64, u64)>,
+}
+
+impl Row {
+    fn new(id: &str, desc: &str, totals: &SequenceTotals, masked: bool) -> Self {
+        let stats = seq_statsu8::seq_stats_from_counts(&totals.bytes, masked);
+        let length = totals.bytes.len() as u64;
+        Row {
+            id: id.to_string(),
+            desc: desc.to_string(),
+            length,
+            non_n_length: length - (totals.bytes.get(b'N') + totals.bytes.get(b'n')) as u64,
+            gc_proportion: stats.gc_proportion,
+            gc_skew: stats.gc_skew,
+            at_skew: stats.at_skew,
+            shannon_entropy: stats.shannon_entropy,
+            n_s: stats.n_s,
+            masked: stats.masked,
+            cpg_s: (totals.dinucs[CG] as f64 / length as f64) as f32,
+            // the dinucleotides are counted in either case, so the bases are too
+            cpg_oe: seq_statsu8::cpg_observed_expected(&totals.bytes.acgt(), totals.dinucs[CG]),
+            gaps: totals.gaps,
+            kmer_shannon: totals
+                .kmers
+                .iter()
+                .map(|(_, counts)| shannon_diversity(counts.iter().copied()))
+                .collect(),
+            n50_n90: None,
+        }
+    }
+
+    // the statistics after the ID (and description), in column order
+    fn fields(&self, kmer_names: &[String]) -> Vec<(String, Value)> {
+        let mut fields = vec![
+            ("length".to_string(), Value::Int(self.length)),
+            ("non_N_length".to_string(), Value::Int(self.non_n_length)),
+            ("GC_prop".to_string(), Value::Prop(self.gc_proportion)),
+            ("GC_skew".to_string(), Value::Prop(self.gc_skew)),
+            ("AT_skew".to_string(), Value::Prop(self.at_skew)),
+            (
+                "Shannon_entropy".to_string(),
+                Value::Float(self.shannon_entropy),
+            ),
+            ("Prop_Ns".to_string(), Value::Prop(self.n_s)),
+            ("Prop_masked".to_string(), Value::Prop(self.masked)),
+            ("CpG_prop".to_string(), Value::Prop(self.cpg_s)),
+            ("CpG_oe".to_string(), Value::Prop(self.cpg_oe)),
+            ("gaps".to_string(), Value::Int(self.gaps)),
+            (
+                "N50".to_string(),
+                self.n50_n90
+                    .map_or(Value::Missing, |(n50, _)| Value::Int(n50)),
+            ),
+            (
+                "N90".to_string(),
+                self.n50_n90
+                    .map_or(Value::Missing, |(_, n90)| Value::Int(n90)),
+            ),
+        ];
+        fields.extend(
+            kmer_names
+                .iter()
+                .zip(&self.kmer_shannon)
+                .map(|(name, &shannon)| (name.clone(), Value::Float(shannon))),
+        );
+        fields
+    }
+}
+
+enum Value {
+    Int(u64),
+    // proportions and skews, which are f32 as in the windows
+    Prop(f32),
+    Float(f64),
+    // N50 and N90 of a single sequence
+    Missing,
+}
+
+impl Value {
+    fn tsv(&self) -> String {
+        match self {
+            Value::Int(n) => n.to_string(),
+            Value::Prop(x) => format!("{x:.3}"),
+            Value::Float(x) => format!("{x:.3}"),
+            Value::Missing => "NA".to_string(),
+        }
+    }
+
+    fn json(&self) -> String {
+        match self {
+            Value::Int(n) => n.to_string(),
+            Value::Prop(x) if x.is_finite() => x.to_string(),
+            Value::Float(x) if x.is_finite() => x.to_string(),
+            _ => "null".to_string(),
+        }
+    }
+}
+
+/// The length `fraction` of the assembly is in sequences at least as
+/// long as, e.g. the N50 for 0.5.
+pub fn nx(lengths: &[u64], fraction: f64) -> u64 {
+    let mut lengths = lengths.to_vec();
+    lengths.sort_unstable_by(|a, b| b.cmp(a));
+    let total: u64 = lengths.iter().sum();
+    let mut sum = 0;
+    for length in lengths {
+        sum += length;
+        if sum as f64 >= total as f64 * fraction {
+            return length;
+        }
+    }
+    0
+}
+
+/// A summary of each sequence and of the whole genome, written as a TSV
+/// and as JSON once every sequence has been seen.
+///
+/// The statistics are over the whole of each sequence rather than
+/// averaged across windows, so k-mers spanning windows are counted too.
+/// The genome row is named `total`.
+pub struct Summary<W: Write> {
+    tsv: W,
+    json: W,
+    description: bool,
+    masked: bool,
+    kmer_names: Vec<String>,
+    rows: Vec<Row>,
+    genome: SequenceTotals,
+}
+
+impl<W: Write> Summary<W> {
+    pub fn new(tsv: W, json: W, kmer_maps: &[KmerMap], description: bool, masked: bool) -> Self {
+        Self {
+            tsv,
+            json,
+            description,
+            masked,
+            kmer_names: kmer_maps
+                .iter()
+                .filter(|m| m.is_dense())
+                .map(|m| format!("{}_Shannon", m.name()))
+                .collect(),
+            rows: Vec::new(),
+            genome: SequenceTotals::empty(kmer_maps),
+        }
+    }
+
+    fn write_tsv(&mut self, rows: &[&Row]) -> Result<()> {
+        let mut header = vec!["ID".to_string()];
+        if self.description {
+            header.push("description".to_string());
+        }
+        let fields = rows[0].fields(&self.kmer_names);
+        header.extend(fields.into_iter().map(|(name, _)| name));
+        writeln!(self.tsv, "{}", header.join("\t"))?;
+
+        for row in rows {
+            let mut line = vec![row.id.clone()];
+            if self.description {
+                line.push(row.desc.clone());
+            }
+            let fields = row.fields(&self.kmer_names);
+            line.extend(fields.iter().map(|(_, value)| value.tsv()));
+            writeln!(self.tsv, "{}", line.join("\t"))?;
+        }
+        Ok(())
+    }
+
+    fn json_object(&self, row: &Row) -> String {
+        let mut fields = vec![format!("\"ID\": {}", json_string(&row.id))];
+        if self.description {
+            fields.push(format!("\"description\": {}", json_string(&row.desc)));
+        }
+        for (name, value) in row.fields(&self.kmer_names) {
+            fields.push(format!("{}: {}", json_string(&name), value.json()));
+        }
+        format!("{{{}}}", fields.join(", "))
+    }
+}
+
+impl<W: Write> WriteWindows for Summary<W> {
+    fn write_record(&mut self, record: &RecordWindows) -> Result<()> {
+        let totals = record
+            .totals
+            .as_ref()
+            .expect("totals are counted for a summary");
+        self.rows
+            .push(Row::new(&record.id, &record.desc, totals, self.masked));
+        self.genome.merge(totals);
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let lengths: Vec<u64> = self.rows.iter().map(|row| row.length).collect();
+        let mut genome = Row::new("total", "", &self.genome, self.masked);
+        genome.n50_n90 = Some((nx(&lengths, 0.5), nx(&lengths, 0.9)));
+
+        let rows = std::mem::take(&mut self.rows);
+        let all: Vec<&Row> = rows.iter().chain([&genome]).collect();
+        self.write_tsv(&all)?;
+
+        let sequences: Vec<String> = rows
+            .iter()
+            .map(|row| format!("    {}", self.json_object(row)))
+            .collect();
+        let json = format!(
+            "{{\n  \"sequences\": [\n{}\n  ],\n  \"total\": {}\n}}\n",
+            sequences.join(",\n"),
+            self.json_object(&genome)
+        );
+        self.json.write_all(json.as_bytes())?;
+
+        self.tsv.flush()?;
+        self.json.flush()?;
+        Ok(())
+    }
+}
+
+// a JSON string literal
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gaps;
+    use crate::kmer_maps::generate_kmer_maps;
+
+    #[test]
+    fn test_nx() {
+        assert_eq!(nx(&[10, 20, 30, 40], 0.5), 30);
+        assert_eq!(nx(&[10, 20, 30, 40], 0.9), 20);
+        assert_eq!(nx(&[100], 0.5), 100);
+        assert_eq!(nx(&[], 0.5), 0);
+    }
+
+    #[test]
+    fn test_blocks_add_up() {
+        let seq: Vec<u8> = (0..BLOCK_BASES as u32 + 1000)
+            .map(|i| b"ACGTNNacgtCG"[(i.wrapping_mul(2_654_435_761) >> 13) as usize % 12])
+            .collect();
+        let kmer_maps = generate_kmer_maps(&[2, 3, 8], true).unwrap();
+        let totals = SequenceTotals::new(&seq, &kmer_maps, 1);
+
+        let mut one_pass = SequenceTotals::empty(&kmer_maps);
+        one_pass.count(&seq, 0..seq.len(), 1);
+        assert_eq!(totals.dinucs, one_pass.dinucs);
+        assert_eq!(totals.gaps, one_pass.gaps);
+        assert_eq!(totals.bytes.len(), seq.len());
+        // the 8-mers are too many to count
+        assert_eq!(totals.kmers.len(), 2);
+        assert_eq!(totals.kmers[1].1, one_pass.kmers[1].1);
+    }
+
+    #[test]
+    fn test_gaps() {
+        let kmer_maps = generate_kmer_maps(&[2], false).unwrap();
+        let totals = SequenceTotals::new(b"NNACGTnNACNGTN", &kmer_maps, 1);
+        assert_eq!(totals.gaps, 4);
+        let row = Row::new("a", "", &totals, false);
+        assert_eq!(row.non_n_length, 8);
+
+        // the same gaps as the BED, even when they cross blocks
+        let seq = b"NNACGTNNNNNACNGTNNNNNNNNACGTNNNNN";
+        for min_gap in 1..7 {
+            let expected = gaps::find_gaps(seq, min_gap).len() as u64;
+            assert_eq!(SequenceTotals::new(seq, &kmer_maps, min_gap).gaps, expected);
+            for split in 0..seq.len() {
+                let mut totals = SequenceTotals::empty(&kmer_maps);
+                totals.count(seq, 0..split, min_gap);
+                totals.count(seq, split..seq.len(), min_gap);
+                assert_eq!(totals.gaps, expected, "min gap {min_gap}, split at {split}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a\"b\t"), "\"a\\\"b\\t\"");
+    }
+}
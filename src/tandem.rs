@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use crate::kmer_maps::ENCODE;
+use crate::kmeru8;
+
+// the k-mers whose spacing gives the period: long enough that chance
+// repeats within a window are rare, and short enough for diverged copies
+const SPACING_K: usize = 12;
+
+/// The dominant tandem repeat of a window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TandemRepeat {
+    // the length of the repeat unit
+    pub period: usize,
+    // the proportion of the window in copies of it
+    pub coverage: f32,
+    // the consensus repeat unit, as its smallest rotation on either strand
+    pub monomer: String,
+}
+
+/// Find the dominant tandem repeat in `win`, if there is one.
+///
+/// Each k-mer is paired with its previous copy in the window, and the most
+/// common spacing between them (the shortest, if tied) is the period. The
+/// bases covered by a k-mer and its copy one period before are the repeat,
+/// and the monomer is the consensus of each position of the period across
+/// it. Fewer than two k-mers at that spacing isn't a repeat.
+pub fn tandem_repeat(win: &[u8]) -> Option<TandemRepeat> {
+    if win.len() < SPACING_K {
+        return None;
+    }
+    let mask = (1u32 << (2 * SPACING_K)) - 1;
+    let mut last_seen: HashMap<u32, usize> = HashMap::with_capacity(win.len());
+    // the spacing back to the previous copy of the k-mer ending at each base
+    let mut spacings = vec![0; win.len()];
+    let (mut code, mut valid) = (0u32, 0);
+    for (i, &b) in win.iter().enumerate() {
+        match ENCODE[b as usize] {
+            4 => valid = 0,
+            base => {
+                code = ((code << 2) | base as u32) & mask;
+                valid += 1;
+            }
+        }
+        if valid >= SPACING_K {
+            if let Some(previous) = last_seen.insert(code, i) {
+                spacings[i] = i - previous;
+            }
+        }
+    }
+
+    let mut counts: HashMap<usize, usize> = HashMap::new();
+    for &spacing in spacings.iter().filter(|&&s| s > 0) {
+        *counts.entry(spacing).or_default() += 1;
+    }
+    let (period, count) = counts
+        .into_iter()
+        .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then(b.cmp(a)))?;
+    if count < 2 {
+        return None;
+    }
+
+    // the k-mers at this spacing cover themselves and their earlier copy
+    let mut covered = vec![false; win.len()];
+    for end in (0..win.len()).filter(|&i| spacings[i] == period) {
+        let start = end + 1 - SPACING_K;
+        covered[start..=end].fill(true);
+        covered[start - period..=end - period].fill(true);
+    }
+    let coverage = covered.iter().filter(|&&c| c).count() as f32 / win.len() as f32;
+
+    // the commonest base at each position of the period, from the first
+    // covered base on
+    let first = covered.iter().position(|&c| c)?;
+    let mut columns = vec![[0usize; 4]; period];
+    for i in (first..win.len()).filter(|&i| covered[i]) {
+        let base = ENCODE[win[i] as usize];
+        if base < 4 {
+            columns[(i - first) % period][base as usize] += 1;
+        }
+    }
+    let monomer: Vec<u8> = columns
+        .iter()
+        .map(|counts| {
+            let base = (0..4).max_by_key(|&b| (counts[b], std::cmp::Reverse(b)));
+            b"ACGT"[base.unwrap_or(0)]
+        })
+        .collect();
+
+    Some(TandemRepeat {
+        period,
+        coverage,
+        monomer: smallest_rotation(&monomer),
+    })
+}
+
+// the lexicographically smallest rotation of the monomer or its reverse
+// complement, so the same repeat reads the same in every window
+fn smallest_rotation(monomer: &[u8]) -> String {
+    let reverse = kmeru8::reverse_complement(monomer);
+    let rotations = |unit: &[u8]| -> Vec<Vec<u8>> {
+        (0..unit.len())
+            .map(|i| [&unit[i..], &unit[..i]].concat())
+            .collect()
+    };
+    let smallest = rotations(monomer)
+        .into_iter()
+        .chain(rotations(&reverse))
+        .min()
+        .unwrap_or_default();
+    String::from_utf8(smallest).expect("monomers are ACGT")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tandem_repeat() {
+        // a 23bp unit, with some copies mutated, in the middle of the window
+        let unit = b"GGTTCAAGCTTTACGCATGACAA";
+        let mut win = b"ACGTTGCAGTCAGCGATGCATCGAATCAGG".repeat(3);
+        for copy in 0..20 {
+            let mut unit = unit.to_vec();
+            if copy % 3 == 0 {
+                unit[copy % 23] = b'A';
+            }
+            win.extend(unit);
+        }
+        win.extend(b"TTGACGATCAGTCGGCATCAGGACTA".repeat(3));
+        let repeat = tandem_repeat(&win).unwrap();
+        assert_eq!(repeat.period, 23);
+        let array = (20 * 23) as f32 / win.len() as f32;
+        assert!(
+            (repeat.coverage - array).abs() < 0.05,
+            "{}",
+            repeat.coverage
+        );
+        assert_eq!(repeat.monomer, smallest_rotation(unit));
+
+        // the same on the other strand
+        assert_eq!(
+            smallest_rotation(b"TTGTCATGCGTAAAGCTTGAACC"),
+            repeat.monomer
+        );
+
+        assert_eq!(tandem_repeat(&b"AT".repeat(50)).unwrap().monomer, "AT");
+        assert_eq!(tandem_repeat(b"ACGTTGCAGTCAGCGATGCATCG"), None);
+    }
+}
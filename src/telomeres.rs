@@ -0,0 +1,361 @@
+use std::io::prelude::*;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+use rayon::prelude::*;
+
+use crate::config::Config;
+use crate::error::check_sequence;
+use crate::input::Input;
+use crate::kmeru8;
+use crate::pipeline::{process_in_order, InFlight};
+use crate::progress::Progress;
+use crate::reader::FastaRecord;
+use crate::windows::SlidingWindows;
+
+/// The telomeric repeat to look for, as it reads on the 3' end of a
+/// chromosome. It's found on either strand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TelomereMotif(Vec<u8>);
+
+impl TelomereMotif {
+    /// The motifs that can be given by the name of the clade.
+    pub const PRESETS: [(&'static str, &'static str); 4] = [
+        ("vertebrate", "TTAGGG"),
+        ("insect", "TTAGG"),
+        ("plant", "TTTAGGG"),
+        ("nematode", "TTAGGC"),
+    ];
+
+    pub fn forward(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The motif as it reads on the 5' end of a chromosome.
+    pub fn reverse(&self) -> Vec<u8> {
+        kmeru8::reverse_complement(&self.0)
+    }
+}
+
+impl Default for TelomereMotif {
+    fn default() -> Self {
+        Self(b"TTAGGG".to_vec())
+    }
+}
+
+impl FromStr for TelomereMotif {
+    type Err = Error;
+
+    /// A preset's name, or the motif itself.
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((_, motif)) = Self::PRESETS.iter().find(|(name, _)| *name == s) {
+            return Ok(Self(motif.as_bytes().to_vec()));
+        }
+        let motif = s.to_ascii_uppercase().into_bytes();
+        if motif.is_empty() || !motif.iter().all(|b| b"ACGT".contains(b)) {
+            let presets: Vec<&str> = Self::PRESETS.iter().map(|(name, _)| *name).collect();
+            bail!(
+                "a telomere motif must be A, C, G and Ts or one of {}, not {s}",
+                presets.join(", ")
+            );
+        }
+        Ok(Self(motif))
+    }
+}
+
+// the bases at each end of a sequence that are checked for telomeric
+// repeat a block at a time, and how much of a block the repeat must cover
+const END_BLOCK: usize = 100;
+const END_BLOCK_COVERED: f64 = 0.5;
+
+/// The telomeric array at one end of a sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TelomereEnd {
+    // the array covers this many bases from the end, 0 if there isn't one
+    pub length: usize,
+    // whether it's mostly the forward motif (true) or its reverse complement
+    pub forward: bool,
+}
+
+/// Where each copy of the motif starts, on either strand. A motif that is
+/// its own reverse complement is only counted as forward, so each site is
+/// counted once.
+struct Hits {
+    forward: Vec<usize>,
+    reverse: Vec<usize>,
+    motif_len: usize,
+    seq_len: usize,
+}
+
+impl Hits {
+    fn new(seq: &[u8], motif: &TelomereMotif) -> Self {
+        let matches = |pattern: &[u8]| -> Vec<usize> {
+            seq.windows(pattern.len())
+                .enumerate()
+                .filter(|(_, kmer)| kmer.eq_ignore_ascii_case(pattern))
+                .map(|(i, _)| i)
+                .collect()
+        };
+        let reverse = motif.reverse();
+        Self {
+            forward: matches(motif.forward()),
+            reverse: match reverse == motif.forward() {
+                true => Vec::new(),
+                false => matches(&reverse),
+            },
+            motif_len: motif.forward().len(),
+            seq_len: seq.len(),
+        }
+    }
+
+    // the copies of either orientation starting in start..end
+    fn within(hits: &[usize], (start, end): (usize, usize)) -> &[usize] {
+        let first = hits.partition_point(|&i| i < start);
+        let last = hits.partition_point(|&i| i < end);
+        &hits[first..last]
+    }
+
+    // the copies of each orientation starting in start..end
+    fn count(&self, range: (usize, usize)) -> (usize, usize) {
+        (
+            Self::within(&self.forward, range).len(),
+            Self::within(&self.reverse, range).len(),
+        )
+    }
+
+    // the telomeric array at the start (or end) of the sequence: the
+    // blocks from that end that are mostly copies of the motif, and the
+    // copies just past them
+    fn end(&self, at_start: bool) -> TelomereEnd {
+        let len = self.seq_len;
+        let blocks = (0..len.div_ceil(END_BLOCK)).map(|b| {
+            let (start, end) = (b * END_BLOCK, ((b + 1) * END_BLOCK).min(len));
+            match at_start {
+                true => (start, end),
+                false => (len - end, len - start),
+            }
+        });
+        let (mut length, mut forward, mut reverse) = (0, 0, 0);
+        for (start, end) in blocks {
+            let (f, r) = self.count((start, end));
+            let covered = ((f + r) * self.motif_len) as f64 / (end - start) as f64;
+            if covered < END_BLOCK_COVERED {
+                break;
+            }
+            let forward_copies = Self::within(&self.forward, (start, end));
+            let reverse_copies = Self::within(&self.reverse, (start, end));
+            let copies = forward_copies.iter().chain(reverse_copies);
+            length = match at_start {
+                true => copies.max().map_or(length, |i| i + self.motif_len),
+                false => copies.min().map_or(length, |i| len - i),
+            };
+            forward += f;
+            reverse += r;
+        }
+        if length > 0 {
+            length = self.extend(length, at_start);
+        }
+        TelomereEnd {
+            length,
+            forward: forward >= reverse,
+        }
+    }
+
+    // carry an array `length` long on past its last block, along copies of
+    // the motif no more than a copy apart
+    fn extend(&self, length: usize, at_start: bool) -> usize {
+        let motif_len = self.motif_len;
+        let mut copies: Vec<usize> = self.forward.iter().chain(&self.reverse).copied().collect();
+        copies.sort_unstable();
+        match at_start {
+            true => {
+                let mut end = length;
+                let first = copies.partition_point(|&i| i + motif_len <= end);
+                for &i in &copies[first..] {
+                    if i > end + motif_len {
+                        break;
+                    }
+                    end = end.max(i + motif_len);
+                }
+                end
+            }
+            false => {
+                let mut start = self.seq_len - length;
+                let last = copies.partition_point(|&i| i < start);
+                for &i in copies[..last].iter().rev() {
+                    if i + 2 * motif_len < start {
+                        break;
+                    }
+                    start = i;
+                }
+                self.seq_len - start
+            }
+        }
+    }
+}
+
+// the results for one sequence: the windows with their counts of the
+// forward and reverse motifs, and the arrays at each end
+struct SequenceTelomeres {
+    id: String,
+    len: usize,
+    windows: Vec<(usize, usize, usize, usize)>,
+    ends: [TelomereEnd; 2],
+}
+
+/// Count the telomeric motif of `config` on both strands in each window,
+/// writing them to `windows`, and report the telomeric arrays at the ends of
+/// each sequence to `report`.
+///
+/// Sequences are written in FASTA order unless `config` gives a sort order.
+/// Only the window size, step, sort order and motif of `config` are used.
+pub fn telomere_windows<W: Write>(
+    config: &Config,
+    input: Input,
+    mut windows: W,
+    mut report: W,
+) -> Result<()> {
+    let motif = &config.telomere_motif;
+    let (forward, reverse) = (motif.forward(), motif.reverse());
+    let (forward, reverse) = (
+        String::from_utf8_lossy(forward),
+        String::from_utf8_lossy(&reverse),
+    );
+    writeln!(windows, "ID\tstart\tend\t{forward}\t{reverse}\tdensity")?;
+    writeln!(
+        report,
+        "ID\tlength\tstart_orientation\tstart_length\tend_orientation\tend_length"
+    )?;
+
+    let mut progress = match config.progress {
+        true => Some(Progress::new(&input)?),
+        false => None,
+    };
+    if config.progress {
+        eprintln!("[+]\tReading fasta (telomere mode)");
+    }
+    let process = |record: FastaRecord| -> Result<SequenceTelomeres> {
+        let seq = &record.seq;
+        check_sequence(&record.id, seq, record.offset)?;
+        let hits = Hits::new(seq, motif);
+        let windows = SlidingWindows::new(seq.len(), config.window_size, config.step)?;
+        let windows = (0..windows.count())
+            .into_par_iter()
+            .map(|i| {
+                let (start, end) = windows.get(i);
+                let (f, r) = hits.count((start, end));
+                (record.offset + start, record.offset + end, f, r)
+            })
+            .collect();
+        Ok(SequenceTelomeres {
+            id: record.id,
+            len: seq.len(),
+            windows,
+            ends: [hits.end(true), hits.end(false)],
+        })
+    };
+
+    let motif_len = motif.forward().len();
+    let mut write = |sequence: &SequenceTelomeres| -> Result<()> {
+        let id = &sequence.id;
+        for &(start, end, f, r) in &sequence.windows {
+            // the proportion of the window in copies of the motif
+            let density = (((f + r) * motif_len) as f64 / (end - start) as f64).min(1.0);
+            writeln!(windows, "{id}\t{start}\t{end}\t{f}\t{r}\t{density:.3}")?;
+        }
+        let [start, end] = sequence.ends.map(|end| {
+            let orientation = match (end.length, end.forward) {
+                (0, _) => ".",
+                (_, true) => "+",
+                (_, false) => "-",
+            };
+            format!("{orientation}\t{}", end.length)
+        });
+        writeln!(report, "{id}\t{}\t{start}\t{end}", sequence.len)?;
+        Ok(())
+    };
+
+    // sorting needs every record before the first can be written.
+    let mut sorted = Vec::new();
+    process_in_order(
+        input,
+        InFlight::default(),
+        |record| record.seq.len(),
+        process,
+        |result| {
+            if let Some(progress) = &mut progress {
+                progress.inc(result.len);
+            }
+            match config.sort {
+                Some(_) => sorted.push(result),
+                None => write(&result)?,
+            }
+            Ok(())
+        },
+    )?;
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+
+    if let Some(order) = config.sort {
+        sorted.sort_by(|a, b| order.compare((&a.id, a.len), (&b.id, b.len)));
+        for result in &sorted {
+            write(result)?;
+        }
+    }
+    windows.flush()?;
+    report.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_motifs() {
+        let motif: TelomereMotif = "vertebrate".parse().unwrap();
+        assert_eq!(motif, TelomereMotif::default());
+        assert_eq!(motif.reverse(), b"CCCTAA");
+        assert_eq!(
+            "ttagg".parse::<TelomereMotif>().unwrap().forward(),
+            b"TTAGG"
+        );
+        assert!("TTNGG".parse::<TelomereMotif>().is_err());
+        assert!("".parse::<TelomereMotif>().is_err());
+    }
+
+    #[test]
+    fn test_ends() {
+        // CCCTAA at the start, 300bp of other sequence, TTAGGG at the end
+        let mut seq = b"CCCTAA".repeat(40);
+        seq.extend(b"ACGTTGCA".repeat(40));
+        seq.extend(b"ttaggg".repeat(30));
+        let motif = TelomereMotif::default();
+        let hits = Hits::new(&seq, &motif);
+        assert_eq!(
+            hits.end(true),
+            TelomereEnd {
+                length: 240,
+                forward: false
+            }
+        );
+        assert_eq!(
+            hits.end(false),
+            TelomereEnd {
+                length: 180,
+                forward: true
+            }
+        );
+        assert_eq!(hits.count((0, 240)), (0, 40));
+
+        let hits = Hits::new(b"ACGTACGTACGT", &motif);
+        assert_eq!(hits.end(true).length, 0);
+
+        // a palindromic motif is counted once per site
+        let motif: TelomereMotif = "TTAA".parse().unwrap();
+        let hits = Hits::new(b"TTAACGTTAAC", &motif);
+        assert_eq!(hits.count((0, 11)), (2, 0));
+    }
+}
@@ -0,0 +1,288 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use crate::bigwig::BigWigWriter;
+use crate::fw::{Entry, RecordWindows, Sink, WriteSeek, WriteWindows};
+use crate::kmer_maps::KmerMap;
+
+/// The kinds of genome browser track that can be written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackFormat {
+    BedGraph,
+    BigWig,
+}
+
+impl TrackFormat {
+    pub const NAMES: [&'static str; 2] = ["bedgraph", "bigwig"];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TrackFormat::BedGraph => "bedGraph",
+            TrackFormat::BigWig => "bw",
+        }
+    }
+}
+
+impl FromStr for TrackFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bedgraph" => Ok(TrackFormat::BedGraph),
+            "bigwig" => Ok(TrackFormat::BigWig),
+            _ => bail!("unknown track format: {s}"),
+        }
+    }
+}
+
+type StatFn = fn(&Entry) -> f64;
+
+// the statistics of a window that can be written as tracks, by their
+// column in the windows file
+const WINDOW_STATS: [(&str, StatFn); 13] = [
+    ("GC_prop", |e| e.gc_proportion as f64),
+    ("GC_skew", |e| e.gc_skew as f64),
+    ("AT_skew", |e| e.at_skew as f64),
+    ("Shannon_entropy", |e| e.shannon_entropy),
+    ("ctw", |e| e.ctw_bpb),
+    ("Prop_Gs", |e| e.g_s as f64),
+    ("Prop_Cs", |e| e.c_s as f64),
+    ("Prop_As", |e| e.a_s as f64),
+    ("Prop_Ts", |e| e.t_s as f64),
+    ("Prop_Ns", |e| e.n_s as f64),
+    ("Prop_masked", |e| e.masked as f64),
+    ("CpG_prop", |e| e.cpg_s as f64),
+    ("CpG_oe", |e| e.cpg_oe as f64),
+];
+
+/// A statistic to write as a track, named as its column in `_freq_windows.tsv`.
+#[derive(Debug, Clone)]
+pub struct TrackStat {
+    pub name: String,
+    value: StatValue,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StatValue {
+    Window(StatFn),
+    // the Shannon diversity of the nth k-mer size
+    KmerShannon(usize),
+}
+
+impl TrackStat {
+    /// The statistic called `name`, given the k-mers counted and whether
+    /// CTW is computed.
+    pub fn parse(name: &str, kmer_maps: &[KmerMap], ctw: bool) -> Result<Self> {
+        if name == "ctw" && !ctw {
+            bail!("a ctw track needs -c/--ctw");
+        }
+        let value = match WINDOW_STATS.iter().find(|(stat, _)| *stat == name) {
+            Some(&(_, value)) => StatValue::Window(value),
+            None => match kmer_maps
+                .iter()
+                .position(|m| format!("{}_Shannon", m.name()) == name)
+            {
+                Some(i) => StatValue::KmerShannon(i),
+                None => {
+                    let kmer_stats = kmer_maps.iter().map(|m| format!("{}_Shannon", m.name()));
+                    let names: Vec<String> = WINDOW_STATS
+                        .iter()
+                        .map(|(stat, _)| stat.to_string())
+                        .chain(kmer_stats)
+                        .collect();
+                    bail!(
+                        "no statistic called {name} to make a track of, expected one of: {}",
+                        names.join(", ")
+                    );
+                }
+            },
+        };
+        Ok(Self {
+            name: name.to_string(),
+            value,
+        })
+    }
+
+    fn value(&self, entry: &Entry) -> f64 {
+        match self.value {
+            StatValue::Window(value) => value(entry),
+            StatValue::KmerShannon(i) => entry.kmers[i].shannon,
+        }
+    }
+}
+
+/// Genome browser tracks of some of the window statistics, one file per
+/// statistic and format.
+///
+/// Tracks can't overlap, so with a step smaller than the window each value
+/// covers its window only up to the start of the next one.
+pub struct Tracks<'a> {
+    tracks: Vec<(TrackStat, TrackWriter<'a>)>,
+}
+
+enum TrackWriter<'a> {
+    BedGraph(Sink<'a>),
+    BigWig(BigWigWriter<Box<dyn WriteSeek + 'a>>),
+}
+
+impl<'a> Tracks<'a> {
+    /// Start a track of each statistic in each format, with the files
+    /// in that order. The bigWig files have to be seekable.
+    pub fn new(
+        stats: &[TrackStat],
+        formats: &[TrackFormat],
+        files: Vec<Sink<'a>>,
+        window_size: usize,
+        step: usize,
+    ) -> Result<Self> {
+        let mut files = files.into_iter();
+        let mut tracks = Vec::new();
+        for stat in stats {
+            for format in formats {
+                let mut file = files.next().expect("one file per track");
+                let writer = match format {
+                    TrackFormat::BedGraph => {
+                        writeln!(file, "track type=bedGraph name=\"{}\"", stat.name)?;
+                        TrackWriter::BedGraph(file)
+                    }
+                    TrackFormat::BigWig => TrackWriter::BigWig(BigWigWriter::new(
+                        file.into_seekable()?,
+                        step.min(window_size),
+                    )?),
+                };
+                tracks.push((stat.clone(), writer));
+            }
+        }
+        Ok(Self { tracks })
+    }
+}
+
+impl WriteWindows for Tracks<'_> {
+    fn write_record(&mut self, record: &RecordWindows) -> Result<()> {
+        let id = &record.id;
+        for (stat, writer) in &mut self.tracks {
+            let next_starts = record.entries.iter().skip(1).map(|e| e.start);
+            for (entry, next_start) in record
+                .entries
+                .iter()
+                .zip(next_starts.map(Some).chain([None]))
+            {
+                let start = entry.start;
+                let end = next_start.map_or(entry.end, |next| next.min(entry.end));
+                let value = stat.value(entry);
+                // as for UCSC's tools, windows without a value (all Ns,
+                // or no G or C for a skew) are left out
+                if !value.is_finite() {
+                    continue;
+                }
+                match writer {
+                    TrackWriter::BedGraph(file) => {
+                        writeln!(file, "{id}\t{start}\t{end}\t{value:.3}")?
+                    }
+                    TrackWriter::BigWig(bigwig) => {
+                        bigwig.add(id, record.full_len, start, end, value as f32)?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        for (_, writer) in self.tracks {
+            match writer {
+                TrackWriter::BedGraph(mut file) => file.flush()?,
+                TrackWriter::BigWig(bigwig) => bigwig.finish()?.flush()?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::fw::fasta_windows;
+    use crate::input::Input;
+    use crate::kmer_maps::generate_kmer_maps;
+
+    #[test]
+    fn test_parse_track_stats() {
+        let kmer_maps = generate_kmer_maps(&[2, 5], false).unwrap();
+        assert!(TrackStat::parse("GC_skew", &kmer_maps, false).is_ok());
+        assert!(TrackStat::parse("5mer_Shannon", &kmer_maps, false).is_ok());
+        assert!(TrackStat::parse("Trinucleotide_Shannon", &kmer_maps, false).is_err());
+        assert!(TrackStat::parse("GC", &kmer_maps, false).is_err());
+        // CTW is only computed when asked for
+        assert!(TrackStat::parse("ctw", &kmer_maps, false).is_err());
+        assert!(TrackStat::parse("ctw", &kmer_maps, true).is_ok());
+    }
+
+    #[test]
+    fn test_skip_missing_values() {
+        let seq = [b"ACGT".repeat(25), b"N".repeat(100), b"ACGT".repeat(25)].concat();
+        let fasta = [b">chr1\n".to_vec(), seq, b"\n".to_vec()].concat();
+        let config = Config::builder()
+            .window_size(50)
+            .tracks(["GC_prop"])
+            .track_formats(vec![TrackFormat::BedGraph, TrackFormat::BigWig])
+            .build()
+            .unwrap();
+        let suffixes = config.output_suffixes();
+        let mut sinks = vec![Cursor::new(Vec::new()); suffixes.len()];
+        let input = Input::from_reader(Cursor::new(fasta)).unwrap();
+        fasta_windows(
+            &config,
+            input,
+            sinks.iter_mut().map(Sink::seekable).collect(),
+        )
+        .unwrap();
+        let sink = |suffix: &str| {
+            let i = suffixes.iter().position(|s| s == suffix).unwrap();
+            sinks[i].get_ref().clone()
+        };
+
+        let bedgraph = String::from_utf8(sink("GC_prop.bedGraph")).unwrap();
+        let lines: Vec<&str> = bedgraph.lines().skip(1).collect();
+        assert_eq!(
+            lines,
+            [
+                "chr1\t0\t50\t0.500",
+                "chr1\t50\t100\t0.500",
+                "chr1\t200\t250\t0.500",
+                "chr1\t250\t300\t0.500"
+            ]
+        );
+
+        // the total summary: bases covered, min, max, sum and sum of squares
+        let bigwig = sink("GC_prop.bw");
+        let at = u64::from_le_bytes(bigwig[44..52].try_into().unwrap()) as usize;
+        let f64_at = |i: usize| f64::from_le_bytes(bigwig[i..i + 8].try_into().unwrap());
+        assert_eq!(
+            u64::from_le_bytes(bigwig[at..at + 8].try_into().unwrap()),
+            200
+        );
+        assert_eq!((f64_at(at + 8), f64_at(at + 16)), (0.5, 0.5));
+        assert_eq!((f64_at(at + 24), f64_at(at + 32)), (100.0, 50.0));
+    }
+
+    #[test]
+    fn test_bigwig_needs_seek() {
+        let config = Config::builder()
+            .tracks(["GC_prop"])
+            .track_formats(vec![TrackFormat::BigWig])
+            .build()
+            .unwrap();
+        let sinks = (0..config.output_suffixes().len())
+            .map(|_| Sink::stream(std::io::sink()))
+            .collect();
+        let input = Input::from_reader(Cursor::new(b">chr1\nACGT\n".to_vec())).unwrap();
+        let err = fasta_windows(&config, input, sinks).unwrap_err();
+        assert!(err.to_string().contains("can seek"), "{err}");
+    }
+}
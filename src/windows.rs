@@ -0,0 +1,160 @@
+use std::ops::Range;
+
+use anyhow::{ensure, Result};
+
+/// Window coordinates over a sequence of length `len`.
+///
+/// Windows of `size` bases start every `step` bases. With `step == size`
+/// (the default) these are the non-overlapping tiles of `chunks(size)`;
+/// with `step < size` they overlap, and with `step > size` they are spaced
+/// out. The last window is truncated at the end of the sequence, and no
+/// window is emitted once one has already reached the end.
+#[derive(Debug, Clone, Copy)]
+pub struct SlidingWindows {
+    len: usize,
+    size: usize,
+    step: usize,
+}
+
+impl SlidingWindows {
+    pub fn new(len: usize, size: usize, step: usize) -> Result<Self> {
+        ensure!(size > 0, "window size must be greater than zero");
+        ensure!(step > 0, "step must be greater than zero");
+        Ok(Self { len, size, step })
+    }
+
+    /// The number of windows over the sequence.
+    pub fn count(&self) -> usize {
+        if self.len == 0 {
+            return 0;
+        }
+        // the first window to reach the end is the last one, unless
+        // the step is so large that we run off the end before that.
+        let to_end = self.len.saturating_sub(self.size).div_ceil(self.step) + 1;
+        to_end.min(self.len.div_ceil(self.step))
+    }
+
+    /// The half-open coordinates of the `i`th window.
+    pub fn get(&self, i: usize) -> (usize, usize) {
+        let start = i * self.step;
+        (start, (start + self.size).min(self.len))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..self.count()).map(|i| self.get(i))
+    }
+}
+
+/// Move a half-open range of positions from `old` to `new`, calling
+/// `update(pos, -1)` on positions that drop out and `update(pos, 1)` on
+/// positions that come in.
+///
+/// Returns false (without calling `update`) when the ranges do not overlap,
+/// so the caller can reset its counts rather than removing one by one.
+pub fn slide_range(
+    old: (usize, usize),
+    new: (usize, usize),
+    mut update: impl FnMut(usize, i32),
+) -> bool {
+    let Some(changes) = range_changes(old, new) else {
+        return false;
+    };
+    for (positions, delta) in changes {
+        for pos in positions {
+            update(pos, delta);
+        }
+    }
+    true
+}
+
+/// The runs of positions that drop out (-1) and come in (1) as a half-open
+/// range moves from `old` to `new`, removals first. Any of them may be
+/// empty. `None` if the ranges do not overlap.
+pub fn range_changes(old: (usize, usize), new: (usize, usize)) -> Option<[(Range<usize>, i32); 4]> {
+    let (old_start, old_end) = old;
+    let (new_start, new_end) = new;
+    if new_start >= old_end || old_start >= new_end {
+        return None;
+    }
+    Some([
+        (old_start..new_start, -1),
+        (new_end..old_end, -1),
+        (new_start..old_start, 1),
+        (old_end..new_end, 1),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coords(len: usize, size: usize, step: usize) -> Vec<(usize, usize)> {
+        SlidingWindows::new(len, size, step)
+            .unwrap()
+            .iter()
+            .collect()
+    }
+
+    #[test]
+    fn test_tiles_match_chunks() {
+        let seq = vec![0u8; 2500];
+        let chunks: Vec<(usize, usize)> = seq
+            .chunks(1000)
+            .enumerate()
+            .map(|(i, c)| (i * 1000, i * 1000 + c.len()))
+            .collect();
+        assert_eq!(coords(2500, 1000, 1000), chunks);
+    }
+
+    #[test]
+    fn test_overlapping() {
+        assert_eq!(
+            coords(2500, 1000, 500),
+            vec![(0, 1000), (500, 1500), (1000, 2000), (1500, 2500)]
+        );
+        // the last window is truncated only if the step doesn't land on the end
+        assert_eq!(
+            coords(2600, 1000, 500),
+            vec![
+                (0, 1000),
+                (500, 1500),
+                (1000, 2000),
+                (1500, 2500),
+                (2000, 2600)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gapped_and_short() {
+        assert_eq!(coords(2500, 1000, 2000), vec![(0, 1000), (2000, 2500)]);
+        assert_eq!(coords(2000, 1000, 2000), vec![(0, 1000)]);
+        assert_eq!(coords(300, 1000, 10), vec![(0, 300)]);
+        assert_eq!(coords(0, 1000, 1000), vec![]);
+    }
+
+    #[test]
+    fn test_zero_is_an_error() {
+        assert!(SlidingWindows::new(10, 0, 1).is_err());
+        assert!(SlidingWindows::new(10, 1, 0).is_err());
+    }
+
+    fn slide(old: (usize, usize), new: (usize, usize)) -> Option<(Vec<usize>, Vec<usize>)> {
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        let overlaps = slide_range(old, new, |pos, delta| match delta {
+            1 => added.push(pos),
+            _ => removed.push(pos),
+        });
+        overlaps.then_some((removed, added))
+    }
+
+    #[test]
+    fn test_slide_range() {
+        assert_eq!(slide((0, 5), (2, 7)), Some((vec![0, 1], vec![5, 6])));
+        // ranges can shrink, e.g. k-mer starts in a truncated last window
+        assert_eq!(slide((4, 10), (6, 8)), Some((vec![4, 5, 8, 9], vec![])));
+        assert_eq!(slide((0, 5), (5, 10)), None);
+        assert_eq!(slide((0, 5), (0, 5)), Some((vec![], vec![])));
+    }
+}